@@ -0,0 +1,632 @@
+//! Tokio based alternative to [`Server`](crate::Server), enabled with the `async` feature.
+//!
+//! Handlers are async and run concurrently, each request in its own task. Instead of
+//! registering callbacks they simply `await` rpc replies with [`Node::rpc`], which makes
+//! protocols with several round trips read like straight-line code. Like with the sync router,
+//! handlers reply through the [`AsyncContext`] of the request. Shared state is passed to every
+//! handler as `Arc<S>`, use a mutex for anything mutable and don't hold it across awaits.
+
+use std::{
+    collections::HashMap,
+    future::Future,
+    io,
+    pin::Pin,
+    sync::{
+        Arc, Mutex, RwLock,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use tokio::{
+    io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+    sync::{mpsc, oneshot},
+    task::{JoinHandle, JoinSet},
+    time::{self, Instant},
+};
+
+use crate::{
+    error, log,
+    messages::{Body, Error, ErrorCode, Init, InitOk, Message, MessageType},
+    router::{HandlerError, HandlerResult},
+    rpc::RpcOptions,
+    sort_node_ids, warn,
+};
+
+type BoxFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+type AsyncHandlerFn<S> = dyn Fn(Message, Node, Arc<S>) -> BoxFuture + Send + Sync;
+
+struct NodeInner {
+    /// Own node id and all node ids, empty until `init` was received.
+    membership: RwLock<(String, Vec<String>)>,
+    counter: AtomicU64,
+    tx_output: mpsc::UnboundedSender<Message>,
+    /// Rpcs waiting for their reply, by `msg_id` of the request.
+    pending: Mutex<HashMap<u64, oneshot::Sender<Message>>>,
+    /// Set on `init`, for the log records of the node.
+    log_node_id: log::NodeId,
+}
+
+/// Handle to the running node, cheap to clone and to move into tasks.
+#[derive(Clone)]
+pub struct Node {
+    inner: Arc<NodeInner>,
+}
+
+impl Node {
+    fn new(tx_output: mpsc::UnboundedSender<Message>) -> Self {
+        Self {
+            inner: Arc::new(NodeInner {
+                membership: RwLock::new((String::new(), Vec::new())),
+                counter: AtomicU64::new(0),
+                tx_output,
+                pending: Mutex::new(HashMap::new()),
+                log_node_id: log::NodeId::default(),
+            }),
+        }
+    }
+
+    fn init(&self, node_id: String, mut node_ids: Vec<String>) {
+        sort_node_ids(&mut node_ids);
+        let _ = self.inner.log_node_id.set(node_id.clone());
+        *self.inner.membership.write().unwrap() = (node_id, node_ids);
+    }
+
+    pub fn node_id(&self) -> String {
+        self.inner.membership.read().unwrap().0.clone()
+    }
+
+    /// All nodes of the cluster including this one, in the same order on every node.
+    pub fn node_ids(&self) -> Vec<String> {
+        self.inner.membership.read().unwrap().1.clone()
+    }
+
+    /// All nodes of the cluster except this one, in the same order on every node.
+    pub fn other_node_ids(&self) -> Vec<String> {
+        let (node_id, node_ids) = &*self.inner.membership.read().unwrap();
+        node_ids
+            .iter()
+            .filter(|other| *other != node_id)
+            .cloned()
+            .collect()
+    }
+
+    pub fn generate_id(&self) -> u64 {
+        self.inner.counter.fetch_add(1, Ordering::Relaxed)
+    }
+
+    pub fn create_message(&self, dest: &str, body: Body) -> Message {
+        Message::new(self.node_id(), dest, body)
+    }
+
+    /// Sends `body` to `dest` without waiting for anything.
+    pub fn send(&self, dest: &str, body: Body) {
+        self.send_message(self.create_message(dest, body));
+    }
+
+    fn send_message(&self, msg: Message) {
+        // only fails once the server stopped, then there is no one left to send to anyway
+        let _ = self.inner.tx_output.send(msg);
+    }
+
+    /// Sends `body` to `dest` with a fresh `msg_id` and waits for the reply. `error` replies and
+    /// timeouts are returned as [`Error`], like [`Maelstrom::rpc`](crate::Maelstrom::rpc) does.
+    pub async fn rpc(
+        &self,
+        dest: &str,
+        mut body: Body,
+        options: RpcOptions,
+    ) -> Result<Body, Error> {
+        let msg_id = self.generate_id();
+        body.set_msg_id(msg_id);
+        let request = self.create_message(dest, body);
+        let (tx_reply, mut rx_reply) = oneshot::channel();
+        self.inner.pending.lock().unwrap().insert(msg_id, tx_reply);
+        // also when the caller drops this future, e.g. in a `select!`
+        let _pending = PendingRpc { node: self, msg_id };
+
+        let deadline = Instant::now() + options.timeout;
+        let reply = loop {
+            self.send_message(request.clone());
+            let resend_at = options.retry_interval.map_or(deadline, |retry_interval| {
+                (Instant::now() + retry_interval).min(deadline)
+            });
+            match time::timeout_at(resend_at, &mut rx_reply).await {
+                Ok(reply) => break reply.ok(),
+                Err(_) if deadline <= Instant::now() => break None,
+                Err(_) => continue,
+            }
+        };
+
+        match reply.map(|reply| reply.body) {
+            Some(Body::Error(error)) => Err(error),
+            Some(body) => Ok(body),
+            None => Err(Error {
+                in_reply_to: msg_id,
+                code: ErrorCode::Timeout,
+                text: format!("no reply from {} after {:?}", dest, options.timeout),
+            }),
+        }
+    }
+
+    /// Runs `task` in the background, e.g. a loop gossiping periodically. Background tasks are
+    /// not waited for when the server stops.
+    pub fn spawn<F>(&self, task: F) -> JoinHandle<()>
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        tokio::spawn(task)
+    }
+
+    /// Hands the reply to the waiting rpc, returns the message back if no rpc waits for it.
+    fn complete_rpc(&self, msg: Message) -> Option<Message> {
+        let Some(in_reply_to) = msg.body.in_reply_to() else {
+            return Some(msg);
+        };
+        match self.inner.pending.lock().unwrap().remove(&in_reply_to) {
+            Some(tx_reply) => {
+                let _ = tx_reply.send(msg);
+                None
+            }
+            None => Some(msg),
+        }
+    }
+}
+
+/// The request an async handler is answering, with the [`Node`] to talk to the cluster. Owned
+/// and cheap to clone, so it can be moved into spawned tasks to reply from there.
+#[derive(Clone)]
+pub struct AsyncContext {
+    src: String,
+    msg_id: Option<u64>,
+    node: Node,
+}
+
+impl AsyncContext {
+    /// Node or client the message came from.
+    pub fn src(&self) -> &str {
+        &self.src
+    }
+
+    pub fn msg_id(&self) -> Option<u64> {
+        self.msg_id
+    }
+
+    pub fn node(&self) -> &Node {
+        &self.node
+    }
+
+    /// Sends `body` back to the sender with `in_reply_to` set to the request's `msg_id`. Messages
+    /// without `msg_id` expect no reply, replies to them are dropped.
+    pub fn reply(&self, mut body: Body) {
+        let Some(msg_id) = self.msg_id else {
+            warn!(
+                "Dropping reply to {}, the request has no msg_id: {:?}",
+                self.src, body
+            );
+            return;
+        };
+        body.set_in_reply_to(msg_id);
+        self.node.send(&self.src, body);
+    }
+
+    /// Replies with a Maelstrom `error` instead.
+    pub fn reply_error(&self, code: ErrorCode, text: impl Into<String>) {
+        let body = Body::Error(Error {
+            in_reply_to: 0,
+            code,
+            text: text.into(),
+        });
+        self.reply(body);
+    }
+}
+
+/// Removes the rpc from [`NodeInner::pending`] once [`Node::rpc`] is done waiting for it.
+struct PendingRpc<'a> {
+    node: &'a Node,
+    msg_id: u64,
+}
+
+impl Drop for PendingRpc<'_> {
+    fn drop(&mut self) {
+        // a poisoned lock must not turn into a panic while unwinding
+        if let Ok(mut pending) = self.node.inner.pending.lock() {
+            pending.remove(&self.msg_id);
+        }
+    }
+}
+
+/// Async counterpart of [`Router`](crate::router::Router), `init` is handled out of the box.
+pub struct AsyncRouter<S> {
+    handlers: HashMap<String, Arc<AsyncHandlerFn<S>>>,
+}
+
+impl<S: Send + Sync + 'static> Default for AsyncRouter<S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S: Send + Sync + 'static> AsyncRouter<S> {
+    pub fn new() -> Self {
+        let mut router = Self {
+            handlers: HashMap::new(),
+        };
+        router.on(|init: Init, ctx, _| async move {
+            ctx.node().init(init.node_id, init.node_ids);
+            let body = Body::InitOk(InitOk {
+                in_reply_to: init.msg_id,
+            });
+            ctx.reply(body);
+            Ok(())
+        });
+        router
+    }
+
+    /// Registers the handler for messages whose body `type` is `M::TYPE`, replacing any
+    /// previously registered handler for that type. Errors and panics are answered with an
+    /// `error` reply like [`Router::on`](crate::router::Router::on) does.
+    pub fn on<M, F, Fut>(&mut self, handler: F)
+    where
+        M: MessageType + Send + 'static,
+        F: Fn(M, AsyncContext, Arc<S>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = HandlerResult> + Send + 'static,
+    {
+        let handler = Arc::new(handler);
+        let handler = move |msg: Message, node: Node, state: Arc<S>| -> BoxFuture {
+            let handler = handler.clone();
+            Box::pin(async move {
+                let result = match msg.body.decode::<M>() {
+                    // in a task of its own, so a panic ends up in the `JoinError`
+                    Ok(m) => {
+                        let ctx = AsyncContext {
+                            src: msg.src.clone(),
+                            msg_id: msg.body.msg_id(),
+                            node: node.clone(),
+                        };
+                        tokio::spawn(handler(m, ctx, state))
+                            .await
+                            .unwrap_or_else(|err| {
+                                error!("Handler panicked: {}", err);
+                                Err(HandlerError::new(ErrorCode::Crash, "handler panicked"))
+                            })
+                    }
+                    Err(err) => Err(HandlerError::new(
+                        ErrorCode::MalformedRequest,
+                        format!("malformed {} body: {}", M::TYPE, err),
+                    )),
+                };
+                if let Err(err) = result {
+                    warn!("Could not handle {:?}: {}", msg, err);
+                    if let Some(reply) = msg.create_error_response(err.code, err.text) {
+                        node.send_message(reply);
+                    }
+                }
+            })
+        };
+        self.handlers.insert(M::TYPE.to_string(), Arc::new(handler));
+    }
+
+    fn handle(&self, msg: Message, node: &Node, state: &Arc<S>, tasks: &mut JoinSet<()>) {
+        let Some(msg) = node.complete_rpc(msg) else {
+            return;
+        };
+
+        if let Some(handler) = self.handlers.get(msg.body.type_name()) {
+            tasks.spawn(handler(msg, node.clone(), state.clone()));
+        } else if msg.body.in_reply_to().is_some() {
+            warn!("Unhandled reply from {}: {:?}", msg.src, msg.body);
+        } else {
+            warn!("Unhandled request from {}: {:?}", msg.src, msg.body);
+            let text = "no handler registered for this message type";
+            if let Some(reply) = msg.create_error_response(ErrorCode::NotSupported, text) {
+                node.send_message(reply);
+            }
+        }
+    }
+}
+
+pub struct AsyncServer<S> {
+    router: AsyncRouter<S>,
+    state: Arc<S>,
+}
+
+impl<S: Send + Sync + 'static> AsyncServer<S> {
+    pub fn new(router: AsyncRouter<S>, state: S) -> Self {
+        Self {
+            router,
+            state: Arc::new(state),
+        }
+    }
+
+    /// Serves stdin and stdout, see [`AsyncServer::serve`].
+    pub async fn serve_stdio(self) -> io::Result<()> {
+        self.serve(BufReader::new(tokio::io::stdin()), tokio::io::stdout())
+            .await
+    }
+
+    /// Handles messages until the input is closed, then waits for the running handlers and
+    /// returns once their output has been written.
+    pub async fn serve<R, W>(self, reader: R, mut writer: W) -> io::Result<()>
+    where
+        R: AsyncBufRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        let (tx_output, mut rx_output) = mpsc::unbounded_channel();
+        let node = Node::new(tx_output);
+        // the runtime has a single thread, handlers log from the thread serving the node
+        log::log_node_id(node.inner.log_node_id.clone());
+        let mut tasks = JoinSet::new();
+        let mut lines = reader.lines();
+
+        loop {
+            tokio::select! {
+                line = lines.next_line() => match line? {
+                    Some(line) => match serde_json::from_str::<Message>(&line) {
+                        Ok(msg) => self.router.handle(msg, &node, &self.state, &mut tasks),
+                        Err(err) => error!("Could not parse message {:?}: {}", line, err),
+                    },
+                    None => break,
+                },
+                Some(msg) = rx_output.recv() => write_message(&mut writer, &msg).await?,
+                Some(result) = tasks.join_next(), if !tasks.is_empty() => log_panic(result),
+            }
+        }
+
+        // input is closed, replies to pending rpcs can't arrive anymore and time out
+        while !tasks.is_empty() {
+            tokio::select! {
+                Some(msg) = rx_output.recv() => write_message(&mut writer, &msg).await?,
+                Some(result) = tasks.join_next() => log_panic(result),
+            }
+        }
+        while let Ok(msg) = rx_output.try_recv() {
+            write_message(&mut writer, &msg).await?;
+        }
+        writer.flush().await
+    }
+}
+
+async fn write_message<W: AsyncWrite + Unpin>(writer: &mut W, msg: &Message) -> io::Result<()> {
+    match serde_json::to_string(msg) {
+        Ok(text) => {
+            writer.write_all(text.as_bytes()).await?;
+            writer.write_all(b"\n").await?;
+            writer.flush().await
+        }
+        Err(err) => {
+            error!("Could not convert {:?} to json string: {}", msg, err);
+            Ok(())
+        }
+    }
+}
+
+fn log_panic(result: Result<(), tokio::task::JoinError>) {
+    if let Err(err) = result {
+        error!("Handler failed: {}", err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncWriteExt, BufReader, DuplexStream},
+        sync::mpsc,
+    };
+
+    use crate::{
+        kv::{KvClient, KvService},
+        messages::{Body, Echo, EchoOk, ErrorCode, Message},
+        rpc::RpcOptions,
+    };
+
+    use super::{AsyncRouter, AsyncServer, Node};
+
+    struct Client {
+        input: DuplexStream,
+        output: tokio::io::Lines<BufReader<DuplexStream>>,
+    }
+
+    impl Client {
+        async fn send(&mut self, raw_msg: &str) {
+            self.input.write_all(raw_msg.as_bytes()).await.unwrap();
+            self.input.write_all(b"\n").await.unwrap();
+        }
+
+        async fn receive(&mut self) -> Message {
+            let line = tokio::time::timeout(Duration::from_secs(1), self.output.next_line())
+                .await
+                .expect("no message before timeout")
+                .unwrap()
+                .expect("output closed");
+            serde_json::from_str(&line).unwrap()
+        }
+    }
+
+    fn start<S: Send + Sync + 'static>(router: AsyncRouter<S>, state: S) -> Client {
+        let (input, server_input) = tokio::io::duplex(4096);
+        let (server_output, output) = tokio::io::duplex(4096);
+        tokio::spawn(
+            AsyncServer::new(router, state).serve(BufReader::new(server_input), server_output),
+        );
+        Client {
+            input,
+            output: BufReader::new(output).lines(),
+        }
+    }
+
+    /// Forwards echos to `n2` and answers with whatever `n2` answered.
+    fn forwarding_router(options: RpcOptions) -> AsyncRouter<()> {
+        let mut router = AsyncRouter::new();
+        router.on(move |echo: Echo, ctx, _| async move {
+            let reply = ctx
+                .node()
+                .rpc("n2", Body::Echo(echo.clone()), options)
+                .await;
+            let echo_text = match reply {
+                Ok(Body::EchoOk(echo_ok)) => echo_ok.echo,
+                Ok(body) => format!("unexpected {:?}", body),
+                Err(error) => format!("{:?}", error.code),
+            };
+            let body = Body::EchoOk(EchoOk {
+                msg_id: None,
+                in_reply_to: echo.msg_id,
+                echo: echo_text,
+            });
+            ctx.reply(body);
+            Ok(())
+        });
+        router
+    }
+
+    #[tokio::test]
+    async fn should_await_rpc_replies_inline() {
+        let mut client = start(forwarding_router(RpcOptions::default()), ());
+        client
+            .send(r#"{"src":"c0","dest":"n1","body":{"type":"init","node_id":"n1","node_ids":["n1","n2"],"msg_id":1}}"#)
+            .await;
+        assert_eq!(client.receive().await.body.type_name(), "init_ok");
+
+        client
+            .send(r#"{"src":"c1","dest":"n1","body":{"type":"echo","echo":"ping","msg_id":2}}"#)
+            .await;
+        let request = client.receive().await;
+        assert_eq!((request.src.as_str(), request.dest.as_str()), ("n1", "n2"));
+        client
+            .send(&format!(
+                r#"{{"src":"n2","dest":"n1","body":{{"type":"echo_ok","echo":"pong","in_reply_to":{}}}}}"#,
+                request.body.msg_id().unwrap()
+            ))
+            .await;
+
+        let reply = client.receive().await;
+        assert_eq!(reply.dest, "c1");
+        assert!(
+            matches!(reply.body, Body::EchoOk(echo_ok) if echo_ok.echo == "pong" && echo_ok.in_reply_to == 2)
+        );
+    }
+
+    #[tokio::test]
+    async fn should_reply_crash_when_a_handler_panics() {
+        let mut router = AsyncRouter::new();
+        router.on(|echo: Echo, ctx, _| async move {
+            if echo.echo == "panic" {
+                panic!("asked to");
+            }
+            let body = Body::EchoOk(EchoOk {
+                msg_id: None,
+                in_reply_to: echo.msg_id,
+                echo: echo.echo,
+            });
+            ctx.reply(body);
+            Ok(())
+        });
+        let mut client = start(router, ());
+        client
+            .send(r#"{"src":"c1","dest":"n1","body":{"type":"echo","echo":"panic","msg_id":1}}"#)
+            .await;
+        let reply = client.receive().await;
+        assert!(
+            matches!(reply.body, Body::Error(error) if error.code == ErrorCode::Crash && error.in_reply_to == 1)
+        );
+
+        client
+            .send(r#"{"src":"c1","dest":"n1","body":{"type":"echo","echo":"ping","msg_id":2}}"#)
+            .await;
+        let reply = client.receive().await;
+        assert!(matches!(reply.body, Body::EchoOk(echo_ok) if echo_ok.echo == "ping"));
+    }
+
+    #[tokio::test]
+    async fn should_reply_errors_to_the_request() {
+        let mut router: AsyncRouter<()> = AsyncRouter::new();
+        router.on(|_: Echo, ctx, _| async move {
+            ctx.reply_error(ErrorCode::TemporarilyUnavailable, "try again");
+            Ok(())
+        });
+        let mut client = start(router, ());
+        client
+            .send(r#"{"src":"c1","dest":"n1","body":{"type":"echo","echo":"ping","msg_id":3}}"#)
+            .await;
+        let reply = client.receive().await;
+        assert_eq!(reply.dest, "c1");
+        assert!(
+            matches!(reply.body, Body::Error(error) if error.code == ErrorCode::TemporarilyUnavailable && error.in_reply_to == 3)
+        );
+    }
+
+    #[tokio::test]
+    async fn should_report_rpc_timeouts_after_retrying() {
+        let options = RpcOptions::default()
+            .with_timeout(Duration::from_millis(200))
+            .with_retry_interval(Duration::from_millis(50));
+        let mut client = start(forwarding_router(options), ());
+        client
+            .send(r#"{"src":"c1","dest":"n1","body":{"type":"echo","echo":"ping","msg_id":2}}"#)
+            .await;
+
+        let mut attempts = 0;
+        let reply = loop {
+            let msg = client.receive().await;
+            if msg.dest == "n2" {
+                attempts += 1;
+            } else {
+                break msg;
+            }
+        };
+        assert!(attempts > 1, "expected retries, got {} attempts", attempts);
+        assert!(
+            matches!(reply.body, Body::EchoOk(echo_ok) if echo_ok.echo == format!("{:?}", ErrorCode::Timeout))
+        );
+    }
+
+    #[tokio::test]
+    async fn should_forget_rpcs_whose_caller_gave_up() {
+        let (tx_output, mut rx_output) = mpsc::unbounded_channel();
+        let node = Node::new(tx_output);
+        let echo = Body::Echo(Echo {
+            msg_id: 0,
+            echo: "ping".to_string(),
+        });
+        let rpc = node.rpc("n2", echo, RpcOptions::default());
+        tokio::select! {
+            _ = rpc => panic!("nobody replies to the rpc"),
+            Some(_) = rx_output.recv() => {}
+        }
+        assert!(node.inner.pending.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn should_await_kv_calls_inline() {
+        let mut router = AsyncRouter::new();
+        router.on(|echo: Echo, ctx, _| async move {
+            let kv = KvClient::new(KvService::LinKv);
+            let written = kv.cas_async(ctx.node(), "k", 1, 2, false).await;
+            let body = Body::EchoOk(EchoOk {
+                msg_id: None,
+                in_reply_to: echo.msg_id,
+                echo: format!("{:?}", written),
+            });
+            ctx.reply(body);
+            Ok(())
+        });
+        let mut client = start(router, ());
+        client
+            .send(r#"{"src":"c1","dest":"n1","body":{"type":"echo","echo":"ping","msg_id":2}}"#)
+            .await;
+        let cas = client.receive().await;
+        assert_eq!((cas.dest.as_str(), cas.body.type_name()), ("lin-kv", "cas"));
+        client
+            .send(&format!(
+                r#"{{"src":"lin-kv","dest":"n1","body":{{"type":"error","code":22,"in_reply_to":{}}}}}"#,
+                cas.body.msg_id().unwrap()
+            ))
+            .await;
+
+        let reply = client.receive().await;
+        assert!(
+            matches!(reply.body, Body::EchoOk(echo_ok) if echo_ok.echo == "Err(PreconditionFailed)")
+        );
+    }
+}
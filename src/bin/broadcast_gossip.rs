@@ -0,0 +1,35 @@
+use std::{
+    env,
+    io::{self, BufReader},
+};
+
+use gossip_glomers::{
+    Server,
+    router::Router,
+    workloads::{
+        broadcast::{
+            gossip::{GossipBroadcast, insert_broadcast_gossip_handlers},
+            topology::TopologyStrategy,
+        },
+        init,
+    },
+};
+
+/// Gossips over a star by default, any other [`TopologyStrategy`] with e.g.
+/// `BROADCAST_TOPOLOGY=kary-tree:4`. Maelstrom passes no arguments to the binary but keeps the
+/// environment.
+fn main() -> io::Result<()> {
+    let topology = match env::var("BROADCAST_TOPOLOGY") {
+        Ok(strategy) => strategy
+            .parse()
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?,
+        Err(_) => TopologyStrategy::Star,
+    };
+    let mut router: Router<_> = init::create_router();
+    insert_broadcast_gossip_handlers(&mut router);
+
+    let reader = BufReader::new(io::stdin());
+    let data = GossipBroadcast::default().with_topology(topology);
+    let mut server = Server::new(reader, io::stdout(), router, data).shutdown_on_sigterm()?;
+    server.serve()
+}
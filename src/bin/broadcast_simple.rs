@@ -6,11 +6,12 @@ use gossip_glomers::{
     workloads::{broadcast::insert_broadcast_simple_handlers, init},
 };
 
-fn main() {
+fn main() -> io::Result<()> {
     let mut router: Router<_> = init::create_router();
     insert_broadcast_simple_handlers(&mut router);
 
     let reader = BufReader::new(io::stdin());
-    let mut server = Server::new(reader, io::stdout(), router, Default::default());
-    server.serve();
+    let mut server =
+        Server::new(reader, io::stdout(), router, Default::default()).shutdown_on_sigterm()?;
+    server.serve()
 }
@@ -6,11 +6,11 @@ use gossip_glomers::{
     workloads::{echo::insert_echo_handlers, init},
 };
 
-fn main() {
+fn main() -> io::Result<()> {
     let mut router: Router<()> = init::create_router();
     insert_echo_handlers(&mut router);
 
     let reader = BufReader::new(io::stdin());
-    let mut server = Server::new(reader, io::stdout(), router, ());
-    server.serve();
+    let mut server = Server::new(reader, io::stdout(), router, ()).shutdown_on_sigterm()?;
+    server.serve()
 }
@@ -0,0 +1,24 @@
+use std::io;
+
+use gossip_glomers::{
+    async_server::{AsyncRouter, AsyncServer},
+    messages::{Body, Echo, EchoOk},
+};
+
+fn main() -> io::Result<()> {
+    let mut router: AsyncRouter<()> = AsyncRouter::new();
+    router.on(|echo: Echo, ctx, _| async move {
+        let body = Body::EchoOk(EchoOk {
+            msg_id: None,
+            in_reply_to: echo.msg_id,
+            echo: echo.echo,
+        });
+        ctx.reply(body);
+        Ok(())
+    });
+
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?
+        .block_on(AsyncServer::new(router, ()).serve_stdio())
+}
@@ -0,0 +1,17 @@
+use std::io::{self, BufReader};
+
+use gossip_glomers::{
+    Server,
+    router::Router,
+    workloads::{g_counter::insert_g_counter_handlers, init},
+};
+
+fn main() -> io::Result<()> {
+    let mut router: Router<_> = init::create_router();
+    insert_g_counter_handlers(&mut router);
+
+    let reader = BufReader::new(io::stdin());
+    let mut server =
+        Server::new(reader, io::stdout(), router, Default::default()).shutdown_on_sigterm()?;
+    server.serve()
+}
@@ -2,10 +2,10 @@ use std::io::{self, BufReader};
 
 use gossip_glomers::{Server, router::Router, workloads::init};
 
-fn main() {
+fn main() -> io::Result<()> {
     let router: Router<()> = init::create_router();
 
     let reader = BufReader::new(io::stdin());
-    let mut server = Server::new(reader, io::stdout(), router, ());
-    server.serve();
+    let mut server = Server::new(reader, io::stdout(), router, ()).shutdown_on_sigterm()?;
+    server.serve()
 }
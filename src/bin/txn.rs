@@ -0,0 +1,29 @@
+use std::{
+    env,
+    io::{self, BufReader},
+};
+
+use gossip_glomers::{
+    Server,
+    router::Router,
+    workloads::{
+        init,
+        txn::{Isolation, Txn, insert_txn_handlers},
+    },
+};
+
+/// Read committed by default, read uncommitted with `TXN_ISOLATION=read-uncommitted`. Maelstrom
+/// passes no arguments to the binary but keeps the environment.
+fn main() -> io::Result<()> {
+    let isolation = match env::var("TXN_ISOLATION").as_deref() {
+        Ok("read-uncommitted") => Isolation::ReadUncommitted,
+        _ => Isolation::ReadCommitted,
+    };
+    let mut router: Router<Txn> = init::create_router();
+    insert_txn_handlers(&mut router);
+
+    let reader = BufReader::new(io::stdin());
+    let mut server =
+        Server::new(reader, io::stdout(), router, Txn::new(isolation)).shutdown_on_sigterm()?;
+    server.serve()
+}
@@ -1,16 +1,34 @@
-use std::io::{self, BufReader};
+use std::{
+    env,
+    io::{self, BufReader},
+};
 
 use gossip_glomers::{
     Server,
     router::Router,
-    workloads::{init, unique_id::insert_unique_id_handlers},
+    workloads::{
+        init,
+        unique_id::{Snowflake, insert_snowflake_id_handlers, insert_unique_id_handlers},
+    },
 };
 
-fn main() {
-    let mut router: Router<()> = init::create_router();
-    insert_unique_id_handlers(&mut router);
-
+/// Generates UUIDs by default, compact time ordered ids with `ID_GENERATOR=snowflake`. Maelstrom
+/// passes no arguments to the binary but keeps the environment.
+fn main() -> io::Result<()> {
     let reader = BufReader::new(io::stdin());
-    let mut server = Server::new(reader, io::stdout(), router, ());
-    server.serve();
+    match env::var("ID_GENERATOR").as_deref() {
+        Ok("snowflake") => {
+            let mut router: Router<Snowflake> = init::create_router();
+            insert_snowflake_id_handlers(&mut router);
+            let mut server = Server::new(reader, io::stdout(), router, Snowflake::default())
+                .shutdown_on_sigterm()?;
+            server.serve()
+        }
+        _ => {
+            let mut router: Router<()> = init::create_router();
+            insert_unique_id_handlers(&mut router);
+            let mut server = Server::new(reader, io::stdout(), router, ()).shutdown_on_sigterm()?;
+            server.serve()
+        }
+    }
 }
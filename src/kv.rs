@@ -0,0 +1,508 @@
+use std::{sync::mpsc::Sender, time::Duration};
+
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use serde_json::Value;
+
+use crate::{
+    Maelstrom,
+    messages::{Body, Error, ErrorCode, Message, MessageType},
+    router::HandlerError,
+    rpc::RpcOptions,
+};
+
+#[cfg(feature = "async")]
+use crate::async_server::Node;
+
+/// The key/value services Maelstrom runs next to the nodes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum KvService {
+    SeqKv,
+    LinKv,
+    LwwKv,
+}
+
+impl KvService {
+    pub fn node_id(&self) -> &'static str {
+        match self {
+            KvService::SeqKv => "seq-kv",
+            KvService::LinKv => "lin-kv",
+            KvService::LwwKv => "lww-kv",
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
+pub struct KvRead {
+    pub key: Value,
+}
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
+pub struct KvReadOk {
+    pub value: Value,
+}
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
+pub struct KvWrite {
+    pub key: Value,
+    pub value: Value,
+}
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
+pub struct KvWriteOk {}
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
+pub struct KvCas {
+    pub key: Value,
+    pub from: Value,
+    pub to: Value,
+    #[serde(default)]
+    pub create_if_not_exists: bool,
+}
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
+pub struct KvCasOk {}
+
+impl MessageType for KvRead {
+    const TYPE: &'static str = "read";
+}
+impl MessageType for KvReadOk {
+    const TYPE: &'static str = "read_ok";
+}
+impl MessageType for KvWrite {
+    const TYPE: &'static str = "write";
+}
+impl MessageType for KvWriteOk {
+    const TYPE: &'static str = "write_ok";
+}
+impl MessageType for KvCas {
+    const TYPE: &'static str = "cas";
+}
+impl MessageType for KvCasOk {
+    const TYPE: &'static str = "cas_ok";
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KvError {
+    KeyDoesNotExist,
+    PreconditionFailed,
+    /// Any other error, including timeouts and replies that could not be decoded.
+    Other(Error),
+}
+
+impl From<Error> for KvError {
+    fn from(error: Error) -> Self {
+        match error.code {
+            ErrorCode::KeyDoesNotExist => KvError::KeyDoesNotExist,
+            ErrorCode::PreconditionFailed => KvError::PreconditionFailed,
+            _ => KvError::Other(error),
+        }
+    }
+}
+
+/// Lets handlers pass kv failures on to the client with `?`.
+impl From<KvError> for HandlerError {
+    fn from(error: KvError) -> Self {
+        match error {
+            KvError::KeyDoesNotExist => {
+                HandlerError::new(ErrorCode::KeyDoesNotExist, "key does not exist")
+            }
+            KvError::PreconditionFailed => {
+                HandlerError::new(ErrorCode::PreconditionFailed, "precondition failed")
+            }
+            KvError::Other(error) => error.into(),
+        }
+    }
+}
+
+/// Client for one of Maelstrom's key/value services, every call is an rpc to the service and
+/// the callback is invoked once the reply arrived or the rpc timed out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KvClient {
+    service: KvService,
+    options: RpcOptions,
+}
+
+impl KvClient {
+    pub fn new(service: KvService) -> Self {
+        Self {
+            service,
+            options: RpcOptions::default(),
+        }
+    }
+
+    pub fn with_options(mut self, options: RpcOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn service(&self) -> KvService {
+        self.service
+    }
+
+    pub fn read<U, T, F>(
+        &self,
+        tx: &mut Sender<Message>,
+        maelstrom: &mut Maelstrom<U>,
+        key: impl Into<Value>,
+        callback: F,
+    ) where
+        T: DeserializeOwned,
+        F: FnOnce(Result<T, KvError>, &mut Sender<Message>, &mut Maelstrom<U>, &mut U)
+            + Send
+            + 'static,
+    {
+        let request = KvRead { key: key.into() };
+        self.call(
+            tx,
+            maelstrom,
+            &request,
+            move |result, tx, maelstrom, data| {
+                let result = result.and_then(|read_ok: KvReadOk| {
+                    serde_json::from_value(read_ok.value).map_err(|err| malformed_reply(&err))
+                });
+                callback(result, tx, maelstrom, data)
+            },
+        );
+    }
+
+    pub fn write<U, F>(
+        &self,
+        tx: &mut Sender<Message>,
+        maelstrom: &mut Maelstrom<U>,
+        key: impl Into<Value>,
+        value: impl Into<Value>,
+        callback: F,
+    ) where
+        F: FnOnce(Result<(), KvError>, &mut Sender<Message>, &mut Maelstrom<U>, &mut U)
+            + Send
+            + 'static,
+    {
+        let request = KvWrite {
+            key: key.into(),
+            value: value.into(),
+        };
+        self.call(
+            tx,
+            maelstrom,
+            &request,
+            move |result, tx, maelstrom, data| {
+                callback(result.map(|_: KvWriteOk| ()), tx, maelstrom, data)
+            },
+        );
+    }
+
+    /// Sets `key` to `to` if it currently holds `from`. With `create_if_not_exists` a missing
+    /// key is created with `to` instead of failing with [`KvError::KeyDoesNotExist`].
+    #[allow(clippy::too_many_arguments)]
+    pub fn cas<U, F>(
+        &self,
+        tx: &mut Sender<Message>,
+        maelstrom: &mut Maelstrom<U>,
+        key: impl Into<Value>,
+        from: impl Into<Value>,
+        to: impl Into<Value>,
+        create_if_not_exists: bool,
+        callback: F,
+    ) where
+        F: FnOnce(Result<(), KvError>, &mut Sender<Message>, &mut Maelstrom<U>, &mut U)
+            + Send
+            + 'static,
+    {
+        let request = KvCas {
+            key: key.into(),
+            from: from.into(),
+            to: to.into(),
+            create_if_not_exists,
+        };
+        self.call(
+            tx,
+            maelstrom,
+            &request,
+            move |result, tx, maelstrom, data| {
+                callback(result.map(|_: KvCasOk| ()), tx, maelstrom, data)
+            },
+        );
+    }
+
+    fn call<U, M, R, F>(
+        &self,
+        tx: &mut Sender<Message>,
+        maelstrom: &mut Maelstrom<U>,
+        request: &M,
+        callback: F,
+    ) where
+        M: MessageType,
+        R: MessageType,
+        F: FnOnce(Result<R, KvError>, &mut Sender<Message>, &mut Maelstrom<U>, &mut U)
+            + Send
+            + 'static,
+    {
+        let body = match Body::encode(request) {
+            Ok(body) => body,
+            Err(err) => {
+                // callbacks always run after the call returned, also when nothing was sent
+                let error = unencodable_request(&err);
+                maelstrom.set_timeout(Duration::ZERO, move |tx, maelstrom, data| {
+                    callback(Err(error), tx, maelstrom, data)
+                });
+                return;
+            }
+        };
+        let dest = self.service.node_id();
+        maelstrom.rpc(
+            tx,
+            dest,
+            body,
+            self.options,
+            move |result, tx, maelstrom, data| {
+                let result = match result {
+                    Ok(body) => body.decode::<R>().map_err(|err| malformed_reply(&err)),
+                    Err(error) => Err(KvError::from(error)),
+                };
+                callback(result, tx, maelstrom, data)
+            },
+        );
+    }
+}
+
+/// The same calls for handlers of the [`async_server`](crate::async_server), awaiting the reply
+/// instead of taking a callback.
+#[cfg(feature = "async")]
+impl KvClient {
+    pub async fn read_async<T: DeserializeOwned>(
+        &self,
+        node: &Node,
+        key: impl Into<Value>,
+    ) -> Result<T, KvError> {
+        let read_ok: KvReadOk = self.call_async(node, &KvRead { key: key.into() }).await?;
+        serde_json::from_value(read_ok.value).map_err(|err| malformed_reply(&err))
+    }
+
+    pub async fn write_async(
+        &self,
+        node: &Node,
+        key: impl Into<Value>,
+        value: impl Into<Value>,
+    ) -> Result<(), KvError> {
+        let request = KvWrite {
+            key: key.into(),
+            value: value.into(),
+        };
+        self.call_async(node, &request).await.map(|_: KvWriteOk| ())
+    }
+
+    /// See [`KvClient::cas`].
+    pub async fn cas_async(
+        &self,
+        node: &Node,
+        key: impl Into<Value>,
+        from: impl Into<Value>,
+        to: impl Into<Value>,
+        create_if_not_exists: bool,
+    ) -> Result<(), KvError> {
+        let request = KvCas {
+            key: key.into(),
+            from: from.into(),
+            to: to.into(),
+            create_if_not_exists,
+        };
+        self.call_async(node, &request).await.map(|_: KvCasOk| ())
+    }
+
+    async fn call_async<M: MessageType, R: MessageType>(
+        &self,
+        node: &Node,
+        request: &M,
+    ) -> Result<R, KvError> {
+        let body = Body::encode(request).map_err(|err| unencodable_request(&err))?;
+        let reply = node.rpc(self.service.node_id(), body, self.options).await?;
+        reply.decode::<R>().map_err(|err| malformed_reply(&err))
+    }
+}
+
+fn unencodable_request(err: &serde_json::Error) -> KvError {
+    KvError::Other(Error {
+        in_reply_to: 0,
+        code: ErrorCode::MalformedRequest,
+        text: format!("could not encode kv request: {}", err),
+    })
+}
+
+fn malformed_reply(err: &serde_json::Error) -> KvError {
+    KvError::Other(Error {
+        in_reply_to: 0,
+        code: ErrorCode::MalformedRequest,
+        text: format!("could not decode kv reply: {}", err),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        messages::{Body, Echo, EchoOk},
+        testing,
+        workloads::init::create_router,
+    };
+
+    use super::{KvClient, KvError, KvService};
+
+    fn reply(echo: &Echo, text: String) -> Body {
+        Body::EchoOk(EchoOk {
+            msg_id: None,
+            in_reply_to: echo.msg_id,
+            echo: text,
+        })
+    }
+
+    #[test]
+    fn should_read_missing_key_as_key_does_not_exist() {
+        let mut router = create_router::<()>();
+        router.on(|echo: Echo, ctx, _| {
+            let reply_to = ctx.reply_to();
+            let (tx, maelstrom) = ctx.split();
+            let kv = KvClient::new(KvService::SeqKv);
+            kv.read(
+                tx,
+                maelstrom,
+                echo.echo.clone(),
+                move |result: Result<u64, _>, tx, maelstrom, _| {
+                    reply_to.reply(tx, maelstrom, reply(&echo, format!("{:?}", result)));
+                },
+            );
+            Ok(())
+        });
+        testing::TestServer::from_router(router)
+            .with_kv_store(KvService::SeqKv)
+            .send_str(r#"{"src":"c1","dest":"n1","body":{"type":"echo","msg_id":1,"echo":"x"}}"#)
+            .assert_msg_received_default_timeout(|msg| {
+                matches!(&msg.body, Body::EchoOk(echo_ok)
+                    if echo_ok.echo == format!("{:?}", Err::<u64, _>(KvError::KeyDoesNotExist)))
+            });
+    }
+
+    fn echo_reply(server: &testing::TestServer) -> &str {
+        server
+            .get_messages()
+            .iter()
+            .find_map(|msg| match &msg.body {
+                Body::EchoOk(echo_ok) => Some(echo_ok.echo.as_str()),
+                _ => None,
+            })
+            .expect("echo_ok reply")
+    }
+
+    #[test]
+    fn should_cas_then_read_value() {
+        let mut router = create_router::<()>();
+        router.on(|echo: Echo, ctx, _| {
+            let reply_to = ctx.reply_to();
+            let (tx, maelstrom) = ctx.split();
+            let kv = KvClient::new(KvService::LinKv);
+            kv.cas(
+                tx,
+                maelstrom,
+                "counter",
+                0,
+                5,
+                true,
+                move |created, tx, maelstrom, _| {
+                    kv.cas(
+                        tx,
+                        maelstrom,
+                        "counter",
+                        0,
+                        7,
+                        false,
+                        move |swapped, tx, maelstrom, _| {
+                            kv.read(
+                                tx,
+                                maelstrom,
+                                "counter",
+                                move |read: Result<u64, _>, tx, maelstrom, _| {
+                                    let text = format!("{:?} {:?} {:?}", created, swapped, read);
+                                    reply_to.reply(tx, maelstrom, reply(&echo, text));
+                                },
+                            );
+                        },
+                    );
+                },
+            );
+            Ok(())
+        });
+        let server = testing::TestServer::from_router(router)
+            .with_kv_store(KvService::LinKv)
+            .send_str(r#"{"src":"c1","dest":"n1","body":{"type":"echo","msg_id":1,"echo":""}}"#)
+            .assert_msg_received_default_timeout(|msg| matches!(&msg.body, Body::EchoOk(_)));
+        assert_eq!(
+            echo_reply(&server),
+            format!(
+                "{:?} {:?} {:?}",
+                Ok::<(), KvError>(()),
+                Err::<(), _>(KvError::PreconditionFailed),
+                Ok::<u64, KvError>(5)
+            )
+        );
+    }
+
+    #[test]
+    fn should_write_then_read_value() {
+        let mut router = create_router::<()>();
+        router.on(|echo: Echo, ctx, _| {
+            let reply_to = ctx.reply_to();
+            let (tx, maelstrom) = ctx.split();
+            let kv = KvClient::new(KvService::LwwKv);
+            kv.write(tx, maelstrom, 1, "one", move |written, tx, maelstrom, _| {
+                kv.read(
+                    tx,
+                    maelstrom,
+                    1,
+                    move |read: Result<String, _>, tx, maelstrom, _| {
+                        let text = format!("{:?} {:?}", written, read);
+                        reply_to.reply(tx, maelstrom, reply(&echo, text));
+                    },
+                );
+            });
+            Ok(())
+        });
+        let server = testing::TestServer::from_router(router)
+            .with_kv_store(KvService::LwwKv)
+            .send_str(r#"{"src":"c1","dest":"n1","body":{"type":"echo","msg_id":1,"echo":""}}"#)
+            .assert_msg_received_default_timeout(|msg| matches!(&msg.body, Body::EchoOk(_)));
+        assert_eq!(echo_reply(&server), r#"Ok(()) Ok("one")"#);
+    }
+
+    #[test]
+    fn nodes_should_share_the_kv_store() {
+        let mut cluster = testing::TestCluster::new(2, || {
+            let mut router = create_router::<()>();
+            router.on(|echo: Echo, ctx, _| {
+                let reply_to = ctx.reply_to();
+                let (tx, maelstrom) = ctx.split();
+                let kv = KvClient::new(KvService::SeqKv);
+                if echo.echo == "write" {
+                    kv.write(tx, maelstrom, "x", 3, move |result, tx, maelstrom, _| {
+                        reply_to.reply(tx, maelstrom, reply(&echo, format!("{:?}", result)));
+                    });
+                } else {
+                    kv.read(
+                        tx,
+                        maelstrom,
+                        "x",
+                        move |result: Result<u64, _>, tx, maelstrom, _| {
+                            reply_to.reply(tx, maelstrom, reply(&echo, format!("{:?}", result)));
+                        },
+                    );
+                }
+                Ok(())
+            });
+            router
+        })
+        .with_kv_store(KvService::SeqKv);
+
+        let echo = |text: &str| {
+            Body::Echo(Echo {
+                msg_id: 0,
+                echo: text.to_string(),
+            })
+        };
+        let written = cluster.request("n0", echo("write"));
+        assert!(matches!(written.body, Body::EchoOk(echo_ok) if echo_ok.echo == "Ok(())"));
+        let read = cluster.request("n1", echo("read"));
+        assert!(matches!(read.body, Body::EchoOk(echo_ok) if echo_ok.echo == "Ok(3)"));
+    }
+}
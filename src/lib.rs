@@ -1,46 +1,136 @@
 use std::{
-    fmt::Debug,
-    io::{BufRead, Write},
+    collections::HashMap,
+    fmt::{self, Debug},
+    io::{self, BufRead, Write},
     sync::mpsc::{self, Receiver, Sender},
     thread::{self, JoinHandle},
-    time::Duration,
+    time::Instant,
 };
 
+use signal_hook::{consts::SIGTERM, iterator::Signals};
+
 use messages::{Body, Message};
 use router::Router;
+use rpc::PendingRpc;
+use timer::Timers;
 
+#[cfg(feature = "async")]
+pub mod async_server;
+pub mod kv;
+pub mod log;
 pub mod messages;
+mod rand;
 pub mod router;
+pub mod rpc;
+pub mod timer;
 pub mod workloads;
 
 #[cfg(test)]
 mod testing;
 
+type ShutdownHook<U> = Box<dyn FnOnce(&mut Maelstrom<U>, &mut U)>;
+
 pub struct Server<U>
 where
     U: Debug,
 {
     router: Router<U>,
     user_data: U,
-    maelstrom_data: Maelstrom,
-    rx_input: Receiver<Message>,
+    maelstrom_data: Maelstrom<U>,
+    tx_input: Sender<Input>,
+    rx_input: Receiver<Input>,
     tx_output: Sender<Message>,
+    output_thread: Option<JoinHandle<io::Result<()>>>,
+    shutdown_hook: Option<ShutdownHook<U>>,
+}
+
+/// Everything the serve loop waits for.
+enum Input {
+    Message(Message),
+    /// Stop serving, with the error that made the server stop if there was one.
+    Shutdown(io::Result<()>),
 }
 
-#[derive(Default, Debug)]
-pub struct Maelstrom {
+pub struct Maelstrom<U> {
     node_id: String,
+    node_ids: Vec<String>,
     counter: u64,
+    rpcs: HashMap<u64, PendingRpc<U>>,
+    timers: Timers<U>,
+    /// Set together with `node_id`, for the log records of every thread of the server.
+    log_node_id: log::NodeId,
 }
 
-impl Maelstrom {
-    pub fn create_message(&self, dest: &str, body: Body) -> Message {
-        Message {
-            src: self.node_id.clone(),
-            dest: dest.to_string(),
-            body,
+impl<U> Default for Maelstrom<U> {
+    fn default() -> Self {
+        Self {
+            node_id: String::new(),
+            node_ids: Vec::new(),
+            counter: 0,
+            rpcs: HashMap::new(),
+            timers: Timers::default(),
+            log_node_id: log::NodeId::default(),
         }
     }
+}
+
+impl<U> Debug for Maelstrom<U> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Maelstrom")
+            .field("node_id", &self.node_id)
+            .field("node_ids", &self.node_ids)
+            .field("counter", &self.counter)
+            .field("rpcs", &self.rpcs)
+            .field("timers", &self.timers)
+            .finish()
+    }
+}
+
+impl<U> Maelstrom<U> {
+    pub fn create_message(&self, dest: &str, body: Body) -> Message {
+        Message::new(self.node_id.clone(), dest, body)
+    }
+
+    /// Stores the membership received with `init`. Node ids are sorted by length first, so
+    /// `n2` comes before `n10` and every node agrees on the same order.
+    pub(crate) fn init(&mut self, node_id: String, mut node_ids: Vec<String>) {
+        sort_node_ids(&mut node_ids);
+        let _ = self.log_node_id.set(node_id.clone());
+        self.node_id = node_id;
+        self.node_ids = node_ids;
+    }
+
+    pub fn node_id(&self) -> &str {
+        &self.node_id
+    }
+
+    /// All nodes of the cluster including this one, in the same order on every node.
+    pub fn node_ids(&self) -> &[String] {
+        &self.node_ids
+    }
+
+    /// All nodes of the cluster except this one, in the same order on every node.
+    pub fn other_node_ids(&self) -> impl Iterator<Item = &str> {
+        self.node_ids
+            .iter()
+            .map(String::as_str)
+            .filter(|node_id| *node_id != self.node_id)
+    }
+
+    /// Position of this node in [`Maelstrom::node_ids`], `None` before `init` was received.
+    pub fn node_index(&self) -> Option<usize> {
+        self.node_ids
+            .iter()
+            .position(|node_id| *node_id == self.node_id)
+    }
+
+    /// Earliest point in time a timer fires or a pending rpc has to be resent or timed out.
+    pub(crate) fn next_deadline(&self) -> Option<Instant> {
+        [self.timers.next_deadline(), self.next_rpc_deadline()]
+            .into_iter()
+            .flatten()
+            .min()
+    }
 
     pub fn generate_id(&mut self) -> u64 {
         let id = self.counter;
@@ -49,6 +139,14 @@ impl Maelstrom {
     }
 }
 
+/// Orders node ids by length first, so `n2` comes before `n10`.
+pub(crate) fn sort_node_ids<S: AsRef<str>>(node_ids: &mut [S]) {
+    node_ids.sort_by(|a, b| {
+        let (a, b) = (a.as_ref(), b.as_ref());
+        a.len().cmp(&b.len()).then_with(|| a.cmp(b))
+    });
+}
+
 impl<U> Server<U>
 where
     U: Debug,
@@ -56,75 +154,206 @@ where
     pub fn new<R: BufRead + Send + 'static, W: Write + Send + 'static>(
         reader: R,
         writer: W,
-        router: Router<U>,
+        mut router: Router<U>,
         user_data: U,
     ) -> Self {
+        let mut maelstrom_data = Maelstrom::default();
+        router.start_timers(&mut maelstrom_data);
         let (tx_input, rx_input) = mpsc::channel();
         let (tx_output, rx_output) = mpsc::channel();
-        Self::start_input_thread(reader, tx_input);
-        Self::start_output_thread(writer, rx_output);
+        let log_node_id = &maelstrom_data.log_node_id;
+        Self::start_input_thread(reader, tx_input.clone(), log_node_id.clone());
+        let output_thread =
+            Self::start_output_thread(writer, rx_output, tx_input.clone(), log_node_id.clone());
         Self {
             router,
             user_data,
+            maelstrom_data,
+            tx_input,
             rx_input,
             tx_output,
-            maelstrom_data: Maelstrom::default(),
+            output_thread: Some(output_thread),
+            shutdown_hook: None,
         }
     }
 
+    /// Runs `hook` once [`Server::serve`] stops, before the remaining output is flushed.
+    pub fn on_shutdown<F>(mut self, hook: F) -> Self
+    where
+        F: FnOnce(&mut Maelstrom<U>, &mut U) + 'static,
+    {
+        self.shutdown_hook = Some(Box::new(hook));
+        self
+    }
+
+    /// Shuts the server down cleanly on SIGTERM instead of being killed mid-write.
+    pub fn shutdown_on_sigterm(self) -> io::Result<Self> {
+        let mut signals = Signals::new([SIGTERM])?;
+        let tx_input = self.tx_input.clone();
+        thread::spawn(move || {
+            if signals.forever().next().is_some() {
+                let _ = tx_input.send(Input::Shutdown(Ok(())));
+            }
+        });
+        Ok(self)
+    }
+
     fn start_input_thread<R: BufRead + Send + 'static>(
         mut reader: R,
-        tx_input: Sender<Message>,
+        tx_input: Sender<Input>,
+        log_node_id: log::NodeId,
     ) -> JoinHandle<()> {
         thread::spawn(move || {
+            log::log_node_id(log_node_id);
             let mut line = String::new();
-            loop {
+            let result = loop {
                 line.clear();
                 match reader.read_line(&mut line) {
-                    Ok(0) => break,
-                    Err(_) => break,
+                    Ok(0) => break Ok(()),
+                    Err(err) => break Err(err),
                     Ok(_) => match serde_json::from_str::<Message>(&line) {
-                        Ok(message) => tx_input
-                            .send(message)
-                            .expect("Channel closed, panic will end this thread"),
-                        Err(err) => eprintln!("Could not parse message: {}", err),
+                        Ok(message) => {
+                            if tx_input.send(Input::Message(message)).is_err() {
+                                // the server stopped already
+                                return;
+                            }
+                        }
+                        Err(err) => {
+                            error!("Could not parse message {:?}: {}", line.trim_end(), err)
+                        }
                     },
                 }
-            }
+            };
+            let _ = tx_input.send(Input::Shutdown(result));
         })
     }
 
     fn start_output_thread<W: Write + Send + 'static>(
         mut writer: W,
         rx_output: Receiver<Message>,
-    ) -> JoinHandle<()> {
+        tx_input: Sender<Input>,
+        log_node_id: log::NodeId,
+    ) -> JoinHandle<io::Result<()>> {
         thread::spawn(move || {
-            for message in rx_output {
-                match serde_json::to_string(&message) {
-                    Ok(text) => writeln!(writer, "{}", text).expect("Error writing to output"),
+            log::log_node_id(log_node_id);
+            for message in rx_output.iter() {
+                let text = match serde_json::to_string(&message) {
+                    Ok(text) => text,
                     Err(err) => {
-                        eprintln!("Could not convert {:?} to json string: {}", message, err)
+                        error!("Could not convert {:?} to json string: {}", message, err);
+                        continue;
                     }
+                };
+                if let Err(err) = writeln!(writer, "{}", text).and_then(|_| writer.flush()) {
+                    // e.g. a broken pipe, the error is returned once the server joins this thread.
+                    // Keep draining so handlers can still send until the server stopped.
+                    let _ = tx_input.send(Input::Shutdown(Ok(())));
+                    for _ in rx_output.iter() {}
+                    return Err(err);
                 }
             }
+            writer.flush()
         })
     }
 
-    pub fn serve(&mut self) {
-        loop {
-            if let Ok(msg) = self.rx_input.recv_timeout(Duration::from_millis(50)) {
-                self.router.handle(
+    /// Handles messages until the input is closed, SIGTERM arrives (see
+    /// [`Server::shutdown_on_sigterm`]) or the output can't be written anymore. Returns once all
+    /// output has been written, with the error that stopped the server if there was one.
+    pub fn serve(&mut self) -> io::Result<()> {
+        log::log_node_id(self.maelstrom_data.log_node_id.clone());
+        let result = loop {
+            // sleep until the next message or until the next timer or rpc is due
+            let input = match self.maelstrom_data.next_deadline() {
+                Some(deadline) => self
+                    .rx_input
+                    .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+                    .ok(),
+                // we keep a sender ourselves, so the channel never disconnects
+                None => self.rx_input.recv().ok(),
+            };
+            match input {
+                Some(Input::Message(msg)) => self.router.handle(
                     msg,
                     &mut self.tx_output,
                     &mut self.maelstrom_data,
                     &mut self.user_data,
-                );
+                ),
+                Some(Input::Shutdown(result)) => break result,
+                None => {}
             }
             self.router.tick(
                 &mut self.tx_output,
                 &mut self.maelstrom_data,
                 &mut self.user_data,
             );
+        };
+
+        if let Some(hook) = self.shutdown_hook.take() {
+            hook(&mut self.maelstrom_data, &mut self.user_data);
         }
+
+        // dropping the last sender ends the output thread once everything is written
+        let (closed, _) = mpsc::channel();
+        drop(std::mem::replace(&mut self.tx_output, closed));
+        let flushed = match self.output_thread.take() {
+            Some(output_thread) => output_thread
+                .join()
+                .unwrap_or_else(|_| Err(io::Error::other("output thread panicked"))),
+            None => Ok(()),
+        };
+        result.and(flushed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Cursor, ErrorKind},
+        sync::{Arc, Mutex},
+    };
+
+    use crate::{
+        Server,
+        testing::SenderWrite,
+        workloads::{echo::insert_echo_handlers, init::create_router},
+    };
+
+    const INPUT: &str = concat!(
+        r#"{"src":"c0","dest":"n0","body":{"type":"init","node_id":"n0","node_ids":["n0"],"msg_id":1}}"#,
+        "\n",
+        r#"{"src":"c1","dest":"n0","body":{"type":"echo","echo":"hi","msg_id":2}}"#,
+        "\n",
+    );
+
+    #[test]
+    fn serve_returns_after_input_is_closed() {
+        let (writer, output) = SenderWrite::new();
+        let shut_down = Arc::new(Mutex::new(None));
+        let hook_result = shut_down.clone();
+        let mut router = create_router::<()>();
+        insert_echo_handlers(&mut router);
+        let mut server =
+            Server::new(Cursor::new(INPUT), writer, router, ()).on_shutdown(move |maelstrom, _| {
+                *hook_result.lock().unwrap() = Some(maelstrom.node_id().to_string())
+            });
+
+        server.serve().unwrap();
+
+        // everything sent before the input closed is written out
+        let written: Vec<String> = output.try_iter().collect();
+        assert_eq!(written.len(), 2);
+        assert!(written[0].contains("init_ok"));
+        assert!(written[1].contains("echo_ok"));
+        assert_eq!(*shut_down.lock().unwrap(), Some("n0".to_string()));
+    }
+
+    #[test]
+    fn serve_reports_broken_output() {
+        let (writer, output) = SenderWrite::new();
+        drop(output);
+        let mut server = Server::new(Cursor::new(INPUT), writer, create_router::<()>(), ());
+
+        let err = server.serve().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::BrokenPipe);
     }
 }
@@ -0,0 +1,327 @@
+//! Leveled logging to stderr, Maelstrom keeps stderr of every node in its own log file.
+//!
+//! `GLOMERS_LOG` filters by level and module, e.g. `warn,gossip_glomers::workloads::broadcast=debug`,
+//! the longest matching module prefix wins and everything else logs at `info` and above.
+//! `GLOMERS_LOG_FORMAT=json` writes one json object per line instead of text. Records carry the
+//! node id once the node received `init`, those logged while the server handles a message also
+//! the message's `src` and `msg_id`.
+
+use std::{
+    cell::RefCell,
+    env, fmt,
+    io::{self, Write},
+    str::FromStr,
+    sync::{Arc, OnceLock},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde_json::json;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+            Level::Trace => "trace",
+        }
+    }
+}
+
+impl FromStr for Level {
+    type Err = String;
+
+    fn from_str(level: &str) -> Result<Self, Self::Err> {
+        match level.trim().to_ascii_lowercase().as_str() {
+            "error" => Ok(Level::Error),
+            "warn" => Ok(Level::Warn),
+            "info" => Ok(Level::Info),
+            "debug" => Ok(Level::Debug),
+            "trace" => Ok(Level::Trace),
+            other => Err(format!("unknown log level {:?}", other)),
+        }
+    }
+}
+
+/// Maximum level per module prefix, parsed from `GLOMERS_LOG`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Filter {
+    default: Level,
+    modules: Vec<(String, Level)>,
+}
+
+impl Filter {
+    /// Unknown directives are skipped, a typo must not take the node down.
+    fn parse(directives: &str) -> Self {
+        let mut filter = Filter {
+            default: Level::Info,
+            modules: Vec::new(),
+        };
+        for directive in directives.split(',').map(str::trim) {
+            match directive.split_once('=') {
+                Some((module, level)) => {
+                    if let Ok(level) = level.parse() {
+                        filter.modules.push((module.trim().to_string(), level));
+                    }
+                }
+                None => {
+                    if let Ok(level) = directive.parse() {
+                        filter.default = level;
+                    }
+                }
+            }
+        }
+        filter
+    }
+
+    fn enabled(&self, level: Level, module: &str) -> bool {
+        let max = self
+            .modules
+            .iter()
+            .filter(|(prefix, _)| module == prefix || module.starts_with(&format!("{}::", prefix)))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map_or(self.default, |(_, level)| *level);
+        level <= max
+    }
+}
+
+struct Config {
+    filter: Filter,
+    json: bool,
+}
+
+fn config() -> &'static Config {
+    static CONFIG: OnceLock<Config> = OnceLock::new();
+    CONFIG.get_or_init(|| Config {
+        filter: Filter::parse(&env::var("GLOMERS_LOG").unwrap_or_default()),
+        json: env::var("GLOMERS_LOG_FORMAT").is_ok_and(|format| format == "json"),
+    })
+}
+
+#[derive(Debug, Default, Clone)]
+struct Context {
+    node_id: Option<String>,
+    src: Option<String>,
+    msg_id: Option<u64>,
+}
+
+/// Node id of a server, set on `init`. The server shares it with the threads it spawns, so
+/// records logged by any of them carry it, e.g. also when the input can't be parsed.
+pub(crate) type NodeId = Arc<OnceLock<String>>;
+
+thread_local! {
+    /// `src` and `msg_id` of the message the thread is handling.
+    static CONTEXT: RefCell<Context> = RefCell::new(Context::default());
+    /// Node id of the server the thread belongs to.
+    static NODE_ID: RefCell<Option<NodeId>> = const { RefCell::new(None) };
+}
+
+/// Makes records logged on this thread carry `node_id` once it is set.
+pub(crate) fn log_node_id(node_id: NodeId) {
+    NODE_ID.set(Some(node_id));
+}
+
+fn current_context() -> Context {
+    let node_id = NODE_ID.with_borrow(|node_id| node_id.as_ref()?.get().cloned());
+    CONTEXT.with_borrow(|context| Context {
+        node_id,
+        ..context.clone()
+    })
+}
+
+/// Attaches `src` and `msg_id` of the message being handled to records until dropped.
+pub(crate) struct MessageContext(());
+
+impl MessageContext {
+    pub(crate) fn enter(src: &str, msg_id: Option<u64>) -> Self {
+        CONTEXT.with_borrow_mut(|context| {
+            context.src = Some(src.to_string());
+            context.msg_id = msg_id;
+        });
+        MessageContext(())
+    }
+}
+
+impl Drop for MessageContext {
+    fn drop(&mut self) {
+        CONTEXT.with_borrow_mut(|context| {
+            context.src = None;
+            context.msg_id = None;
+        });
+    }
+}
+
+fn format_record(
+    level: Level,
+    module: &str,
+    context: &Context,
+    args: fmt::Arguments,
+    timestamp_ms: u128,
+    json: bool,
+) -> String {
+    if json {
+        return json!({
+            "ts": timestamp_ms as u64,
+            "level": level.as_str(),
+            "module": module,
+            "node": context.node_id,
+            "src": context.src,
+            "msg_id": context.msg_id,
+            "message": args.to_string(),
+        })
+        .to_string();
+    }
+
+    let mut line = format!(
+        "{} {:5} ",
+        timestamp_ms,
+        level.as_str().to_ascii_uppercase()
+    );
+    if let Some(node_id) = &context.node_id {
+        line += &format!("{} ", node_id);
+    }
+    if let Some(src) = &context.src {
+        line += &format!("src={} ", src);
+    }
+    if let Some(msg_id) = context.msg_id {
+        line += &format!("msg_id={} ", msg_id);
+    }
+    line + &format!("{}: {}", module, args)
+}
+
+/// Use the [`error!`](crate::error), [`warn!`](crate::warn), [`info!`](crate::info),
+/// [`debug!`](crate::debug) and [`trace!`](crate::trace) macros instead.
+pub fn log(level: Level, module: &str, args: fmt::Arguments) {
+    let config = config();
+    if !config.filter.enabled(level, module) {
+        return;
+    }
+    let timestamp_ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis());
+    let context = current_context();
+    let line = format_record(level, module, &context, args, timestamp_ms, config.json);
+    // nowhere left to report a failing stderr
+    let _ = writeln!(io::stderr().lock(), "{}", line);
+}
+
+#[macro_export]
+macro_rules! error {
+    ($($arg:tt)+) => {
+        $crate::log::log($crate::log::Level::Error, module_path!(), format_args!($($arg)+))
+    };
+}
+
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)+) => {
+        $crate::log::log($crate::log::Level::Warn, module_path!(), format_args!($($arg)+))
+    };
+}
+
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)+) => {
+        $crate::log::log($crate::log::Level::Info, module_path!(), format_args!($($arg)+))
+    };
+}
+
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)+) => {
+        $crate::log::log($crate::log::Level::Debug, module_path!(), format_args!($($arg)+))
+    };
+}
+
+#[macro_export]
+macro_rules! trace {
+    ($($arg:tt)+) => {
+        $crate::log::log($crate::log::Level::Trace, module_path!(), format_args!($($arg)+))
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use serde_json::{Value, json};
+
+    use super::{Context, Filter, Level, NodeId, current_context, format_record, log_node_id};
+
+    #[test]
+    fn filter_uses_longest_matching_module_prefix() {
+        let filter = Filter::parse(
+            "warn, gossip_glomers::workloads=debug,gossip_glomers::workloads::txn=error,bogus=loud",
+        );
+        assert_eq!(filter.default, Level::Warn);
+        assert!(!filter.enabled(Level::Info, "gossip_glomers::router"));
+        assert!(filter.enabled(Level::Debug, "gossip_glomers::workloads::broadcast"));
+        assert!(!filter.enabled(Level::Warn, "gossip_glomers::workloads::txn"));
+        // prefixes only match whole module path segments
+        assert!(!filter.enabled(Level::Debug, "gossip_glomers::workloads_extra"));
+        assert_eq!(Filter::parse("").default, Level::Info);
+    }
+
+    #[test]
+    fn records_carry_node_and_message_context() {
+        let context = Context {
+            node_id: Some("n1".to_string()),
+            src: Some("c2".to_string()),
+            msg_id: Some(7),
+        };
+        let text = format_record(
+            Level::Warn,
+            "m",
+            &context,
+            format_args!("hi {}", 1),
+            5,
+            false,
+        );
+        assert_eq!(text, "5 WARN  n1 src=c2 msg_id=7 m: hi 1");
+
+        let line = format_record(
+            Level::Debug,
+            "m",
+            &Context::default(),
+            format_args!("x"),
+            5,
+            true,
+        );
+        let record: Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(
+            record,
+            json!({"ts": 5, "level": "debug", "module": "m", "node": null, "src": null, "msg_id": null, "message": "x"})
+        );
+    }
+
+    #[test]
+    fn node_id_is_logged_by_the_threads_sharing_it() {
+        let node_id = NodeId::default();
+        let other = NodeId::default();
+        log_node_id(node_id.clone());
+        let _ = node_id.set("n1".to_string());
+
+        let shared = node_id.clone();
+        let logged = thread::spawn(move || {
+            log_node_id(shared);
+            current_context().node_id
+        });
+        let unrelated = thread::spawn(move || {
+            log_node_id(other);
+            current_context().node_id
+        });
+        assert_eq!(current_context().node_id, Some("n1".to_string()));
+        assert_eq!(logged.join().unwrap(), Some("n1".to_string()));
+        assert_eq!(unrelated.join().unwrap(), None);
+    }
+}
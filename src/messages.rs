@@ -1,23 +1,55 @@
 use std::collections::HashMap;
 
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer, de::DeserializeOwned};
+use serde_json::{Map, Value};
 impl Message {
-    pub fn create_response(&self, body: Body) -> Message {
+    pub fn new(src: impl Into<String>, dest: impl Into<String>, body: Body) -> Message {
         Message {
-            src: self.dest.clone(),
-            dest: self.src.clone(),
+            id: None,
+            src: src.into(),
+            dest: dest.into(),
             body,
+            extra: Map::new(),
         }
     }
+
+    /// A reply is a new message, it carries neither the `id` nor the extra fields of `self`.
+    pub fn create_response(&self, body: Body) -> Message {
+        Message::new(self.dest.clone(), self.src.clone(), body)
+    }
+
+    /// Builds an `error` reply to this message, `None` if it carries no `msg_id` to reply to.
+    pub fn create_error_response(
+        &self,
+        code: ErrorCode,
+        text: impl Into<String>,
+    ) -> Option<Message> {
+        let in_reply_to = self.body.msg_id()?;
+        Some(self.create_response(Body::Error(Error {
+            in_reply_to,
+            code,
+            text: text.into(),
+        })))
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
 pub struct Message {
+    /// Assigned by Maelstrom to every message it delivers and written out again with the
+    /// message, replies and messages built with [`Message::new`] have none.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<u64>,
     pub src: String,
     pub dest: String,
     pub body: Body,
+    /// Fields of the envelope unknown to us, written out again as they were received.
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
+/// Bodies are parsed into the built-in variant only if it keeps every field of the body,
+/// otherwise into [`Body::Custom`]. A custom `read` carrying a `key` must not lose the key by
+/// being parsed as [`Read`], so the built-in structs deny unknown fields.
 #[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
 #[serde(tag = "type")]
 #[serde(rename_all = "snake_case")]
@@ -34,9 +66,235 @@ pub enum Body {
     BroadcastOk(BroadcastOk),
     Read(Read),
     ReadOk(ReadOk),
+    Add(Add),
+    AddOk(AddOk),
+    Error(Error),
+    /// Any body not matching one of the variants above, including its `type` field.
+    #[serde(untagged)]
+    Custom(Value),
+}
+
+/// A message body that is tagged with `type` on the wire. Implementing it for a struct allows
+/// registering handlers for it with [`Router::on`](crate::router::Router::on) and sending it with
+/// [`Body::encode`], without the struct being part of [`Body`].
+pub trait MessageType: Serialize + DeserializeOwned {
+    const TYPE: &'static str;
+}
+
+/// A typed message together with the body fields `M` does not know about, for handlers that
+/// pass them on, e.g. when forwarding a request or echoing it. `type` is never part of `extra`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Extended<M> {
+    pub message: M,
+    pub extra: Map<String, Value>,
+}
+
+impl<M> Extended<M> {
+    pub fn new(message: M) -> Self {
+        Self {
+            message,
+            extra: Map::new(),
+        }
+    }
+
+    /// `message` with the extra fields of `self`, e.g. a reply carrying the request's fields.
+    pub fn with<T>(&self, message: T) -> Extended<T> {
+        Extended {
+            message,
+            extra: self.extra.clone(),
+        }
+    }
+}
+
+impl<M: MessageType> MessageType for Extended<M> {
+    const TYPE: &'static str = M::TYPE;
+}
+
+/// Fields of `message` always win over extra fields with the same name.
+impl<M: Serialize> Serialize for Extended<M> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut value = serde_json::to_value(&self.message).map_err(serde::ser::Error::custom)?;
+        if let Some(object) = value.as_object_mut() {
+            for (key, field) in &self.extra {
+                if !object.contains_key(key) {
+                    object.insert(key.clone(), field.clone());
+                }
+            }
+        }
+        value.serialize(serializer)
+    }
+}
+
+/// `M` takes the fields it knows, everything else but `type` ends up in `extra`.
+impl<'de, M: Deserialize<'de>> Deserialize<'de> for Extended<M> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        struct Fields<M> {
+            #[serde(flatten)]
+            message: M,
+            #[serde(flatten)]
+            extra: Map<String, Value>,
+        }
+
+        let Fields { message, mut extra } = Fields::deserialize(deserializer)?;
+        extra.remove("type");
+        Ok(Self { message, extra })
+    }
+}
+
+impl Body {
+    /// Converts a typed message into a body, built-in types end up in their own variant and
+    /// everything else in [`Body::Custom`].
+    pub fn encode<M: MessageType>(message: &M) -> serde_json::Result<Body> {
+        Body::raw(M::TYPE, serde_json::to_value(message)?)
+    }
+
+    /// Builds a body of type `type_name` from the json object `fields`, for message types
+    /// without a struct, e.g. `Body::raw("lock", json!({"key": 1}))`.
+    pub fn raw(type_name: &str, mut fields: Value) -> serde_json::Result<Body> {
+        let Some(object) = fields.as_object_mut() else {
+            return Err(serde::de::Error::custom(format!(
+                "{} must be a json object",
+                type_name
+            )));
+        };
+        object.insert("type".to_string(), Value::from(type_name));
+        serde_json::from_value(fields)
+    }
+
+    /// Decodes the body as `M`. Built-in variants are passed on without their `type`, which
+    /// the built-in structs don't accept.
+    pub fn decode<M: MessageType>(&self) -> serde_json::Result<M> {
+        match self {
+            Body::Custom(value) => M::deserialize(value),
+            body => {
+                let mut value = serde_json::to_value(body)?;
+                if let Some(object) = value.as_object_mut() {
+                    object.remove("type");
+                }
+                M::deserialize(value)
+            }
+        }
+    }
+
+    pub fn type_name(&self) -> &str {
+        match self {
+            Body::Init(_) => Init::TYPE,
+            Body::InitOk(_) => InitOk::TYPE,
+            Body::Echo(_) => Echo::TYPE,
+            Body::EchoOk(_) => EchoOk::TYPE,
+            Body::Generate(_) => Generate::TYPE,
+            Body::GenerateOk(_) => GenerateOk::TYPE,
+            Body::Topology(_) => Topology::TYPE,
+            Body::TopologyOk(_) => TopologyOk::TYPE,
+            Body::Broadcast(_) => Broadcast::TYPE,
+            Body::BroadcastOk(_) => BroadcastOk::TYPE,
+            Body::Read(_) => Read::TYPE,
+            Body::ReadOk(_) => ReadOk::TYPE,
+            Body::Add(_) => Add::TYPE,
+            Body::AddOk(_) => AddOk::TYPE,
+            Body::Error(_) => Error::TYPE,
+            Body::Custom(value) => value.get("type").and_then(Value::as_str).unwrap_or(""),
+        }
+    }
+
+    pub fn msg_id(&self) -> Option<u64> {
+        match self {
+            Body::Init(init) => Some(init.msg_id),
+            Body::InitOk(_) => None,
+            Body::Echo(echo) => Some(echo.msg_id),
+            Body::EchoOk(echo_ok) => echo_ok.msg_id,
+            Body::Generate(generate) => Some(generate.msg_id),
+            Body::GenerateOk(generate_ok) => generate_ok.msg_id,
+            Body::Topology(topology) => Some(topology.msg_id),
+            Body::TopologyOk(topology_ok) => topology_ok.msg_id,
+            Body::Broadcast(broadcast) => Some(broadcast.msg_id),
+            Body::BroadcastOk(broadcast_ok) => broadcast_ok.msg_id,
+            Body::Read(read) => Some(read.msg_id),
+            Body::ReadOk(read_ok) => read_ok.msg_id,
+            Body::Add(add) => Some(add.msg_id),
+            Body::AddOk(add_ok) => add_ok.msg_id,
+            Body::Error(_) => None,
+            Body::Custom(value) => value.get("msg_id").and_then(Value::as_u64),
+        }
+    }
+
+    /// Sets the `msg_id` of the body, bodies without a `msg_id` field are left untouched.
+    pub fn set_msg_id(&mut self, msg_id: u64) {
+        match self {
+            Body::Init(init) => init.msg_id = msg_id,
+            Body::Echo(echo) => echo.msg_id = msg_id,
+            Body::EchoOk(echo_ok) => echo_ok.msg_id = Some(msg_id),
+            Body::Generate(generate) => generate.msg_id = msg_id,
+            Body::GenerateOk(generate_ok) => generate_ok.msg_id = Some(msg_id),
+            Body::Topology(topology) => topology.msg_id = msg_id,
+            Body::TopologyOk(topology_ok) => topology_ok.msg_id = Some(msg_id),
+            Body::Broadcast(broadcast) => broadcast.msg_id = msg_id,
+            Body::BroadcastOk(broadcast_ok) => broadcast_ok.msg_id = Some(msg_id),
+            Body::Read(read) => read.msg_id = msg_id,
+            Body::ReadOk(read_ok) => read_ok.msg_id = Some(msg_id),
+            Body::Add(add) => add.msg_id = msg_id,
+            Body::AddOk(add_ok) => add_ok.msg_id = Some(msg_id),
+            Body::InitOk(_) | Body::Error(_) => {}
+            Body::Custom(value) => {
+                if let Some(object) = value.as_object_mut() {
+                    object.insert("msg_id".to_string(), Value::from(msg_id));
+                }
+            }
+        }
+    }
+
+    /// Sets the `in_reply_to` of the body, bodies without an `in_reply_to` field are left
+    /// untouched.
+    pub fn set_in_reply_to(&mut self, in_reply_to: u64) {
+        match self {
+            Body::InitOk(init_ok) => init_ok.in_reply_to = in_reply_to,
+            Body::EchoOk(echo_ok) => echo_ok.in_reply_to = in_reply_to,
+            Body::GenerateOk(generate_ok) => generate_ok.in_reply_to = in_reply_to,
+            Body::TopologyOk(topology_ok) => topology_ok.in_reply_to = in_reply_to,
+            Body::BroadcastOk(broadcast_ok) => broadcast_ok.in_reply_to = in_reply_to,
+            Body::ReadOk(read_ok) => read_ok.in_reply_to = in_reply_to,
+            Body::AddOk(add_ok) => add_ok.in_reply_to = in_reply_to,
+            Body::Error(error) => error.in_reply_to = in_reply_to,
+            Body::Custom(value) => {
+                if let Some(object) = value.as_object_mut() {
+                    object.insert("in_reply_to".to_string(), Value::from(in_reply_to));
+                }
+            }
+            Body::Init(_)
+            | Body::Echo(_)
+            | Body::Generate(_)
+            | Body::Topology(_)
+            | Body::Broadcast(_)
+            | Body::Read(_)
+            | Body::Add(_) => {}
+        }
+    }
+
+    pub fn in_reply_to(&self) -> Option<u64> {
+        match self {
+            Body::InitOk(init_ok) => Some(init_ok.in_reply_to),
+            Body::EchoOk(echo_ok) => Some(echo_ok.in_reply_to),
+            Body::GenerateOk(generate_ok) => Some(generate_ok.in_reply_to),
+            Body::TopologyOk(topology_ok) => Some(topology_ok.in_reply_to),
+            Body::BroadcastOk(broadcast_ok) => Some(broadcast_ok.in_reply_to),
+            Body::ReadOk(read_ok) => Some(read_ok.in_reply_to),
+            Body::AddOk(add_ok) => Some(add_ok.in_reply_to),
+            Body::Error(error) => Some(error.in_reply_to),
+            Body::Custom(value) => value.get("in_reply_to").and_then(Value::as_u64),
+            Body::Init(_)
+            | Body::Echo(_)
+            | Body::Generate(_)
+            | Body::Topology(_)
+            | Body::Broadcast(_)
+            | Body::Read(_)
+            | Body::Add(_) => None,
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct Init {
     pub msg_id: u64,
     pub node_id: String,
@@ -44,15 +302,18 @@ pub struct Init {
 }
 
 #[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct InitOk {
     pub in_reply_to: u64,
 }
 #[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct Echo {
     pub msg_id: u64,
     pub echo: String,
 }
 #[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct EchoOk {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub msg_id: Option<u64>,
@@ -60,103 +321,285 @@ pub struct EchoOk {
     pub echo: String,
 }
 #[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct Generate {
     pub msg_id: u64,
 }
 #[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct GenerateOk {
     pub in_reply_to: u64,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub msg_id: Option<u64>,
-    pub id: String,
+    /// Maelstrom accepts any json value as id, as long as it is unique.
+    pub id: Value,
 }
 #[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct Broadcast {
-    pub message: serde_json::Value,
+    pub message: Value,
     pub msg_id: u64,
 }
 #[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct BroadcastOk {
     pub in_reply_to: u64,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub msg_id: Option<u64>,
 }
 #[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct Read {
     pub msg_id: u64,
 }
 #[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct ReadOk {
     pub in_reply_to: u64,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub msg_id: Option<u64>,
-    pub messages: Vec<serde_json::Value>,
+    pub messages: Vec<Value>,
 }
+/// `read_ok` of the g-counter workload, which shares its `type` with [`ReadOk`] and is
+/// therefore sent and received as [`Body::Custom`].
 #[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
+pub struct CounterReadOk {
+    pub in_reply_to: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub msg_id: Option<u64>,
+    pub value: u64,
+}
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Add {
+    pub msg_id: u64,
+    pub delta: u64,
+}
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct AddOk {
+    pub in_reply_to: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub msg_id: Option<u64>,
+}
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct Topology {
     pub msg_id: u64,
     pub topology: HashMap<String, Vec<String>>,
 }
 #[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct TopologyOk {
     pub in_reply_to: u64,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub msg_id: Option<u64>,
 }
 
-macro_rules! impl_from_body {
-    ($variant:ident) => {
-        impl From<Body> for $variant {
-            fn from(body: Body) -> Self {
-                if let Body::$variant(inner) = body {
-                    inner
-                } else {
-                    panic!("should not happen");
-                }
-            }
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Error {
+    pub in_reply_to: u64,
+    pub code: ErrorCode,
+    #[serde(default)]
+    pub text: String,
+}
+
+/// Error codes defined by the Maelstrom protocol, see
+/// <https://github.com/jepsen-io/maelstrom/blob/main/doc/protocol.md#errors>
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Hash)]
+pub enum ErrorCode {
+    Timeout,
+    NodeNotFound,
+    NotSupported,
+    TemporarilyUnavailable,
+    MalformedRequest,
+    Crash,
+    Abort,
+    KeyDoesNotExist,
+    KeyAlreadyExists,
+    PreconditionFailed,
+    TxnConflict,
+    /// Any code not defined by Maelstrom, custom codes should be 1000 and above.
+    Custom(u64),
+}
+
+impl ErrorCode {
+    pub fn code(&self) -> u64 {
+        match self {
+            ErrorCode::Timeout => 0,
+            ErrorCode::NodeNotFound => 1,
+            ErrorCode::NotSupported => 10,
+            ErrorCode::TemporarilyUnavailable => 11,
+            ErrorCode::MalformedRequest => 12,
+            ErrorCode::Crash => 13,
+            ErrorCode::Abort => 14,
+            ErrorCode::KeyDoesNotExist => 20,
+            ErrorCode::KeyAlreadyExists => 21,
+            ErrorCode::PreconditionFailed => 22,
+            ErrorCode::TxnConflict => 30,
+            ErrorCode::Custom(code) => *code,
+        }
+    }
+
+    pub fn from_code(code: u64) -> Self {
+        match code {
+            0 => ErrorCode::Timeout,
+            1 => ErrorCode::NodeNotFound,
+            10 => ErrorCode::NotSupported,
+            11 => ErrorCode::TemporarilyUnavailable,
+            12 => ErrorCode::MalformedRequest,
+            13 => ErrorCode::Crash,
+            14 => ErrorCode::Abort,
+            20 => ErrorCode::KeyDoesNotExist,
+            21 => ErrorCode::KeyAlreadyExists,
+            22 => ErrorCode::PreconditionFailed,
+            30 => ErrorCode::TxnConflict,
+            code => ErrorCode::Custom(code),
+        }
+    }
+
+    /// Definite errors guarantee that the request had no effect, indefinite ones
+    /// (timeout and crash) may or may not have been applied.
+    pub fn is_definite(&self) -> bool {
+        !matches!(self, ErrorCode::Timeout | ErrorCode::Crash)
+    }
+}
+
+impl Serialize for ErrorCode {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(self.code())
+    }
+}
+
+impl<'de> Deserialize<'de> for ErrorCode {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        u64::deserialize(deserializer).map(ErrorCode::from_code)
+    }
+}
+
+macro_rules! impl_message_type {
+    ($name:ident, $type:literal) => {
+        impl MessageType for $name {
+            const TYPE: &'static str = $type;
         }
     };
 }
 
-impl_from_body!(Init);
-impl_from_body!(InitOk);
-impl_from_body!(Echo);
-impl_from_body!(EchoOk);
-impl_from_body!(Generate);
-impl_from_body!(GenerateOk);
-impl_from_body!(Broadcast);
-impl_from_body!(BroadcastOk);
-impl_from_body!(Read);
-impl_from_body!(ReadOk);
-impl_from_body!(Topology);
-impl_from_body!(TopologyOk);
+impl_message_type!(Init, "init");
+impl_message_type!(InitOk, "init_ok");
+impl_message_type!(Echo, "echo");
+impl_message_type!(EchoOk, "echo_ok");
+impl_message_type!(Generate, "generate");
+impl_message_type!(GenerateOk, "generate_ok");
+impl_message_type!(Broadcast, "broadcast");
+impl_message_type!(BroadcastOk, "broadcast_ok");
+impl_message_type!(Read, "read");
+impl_message_type!(ReadOk, "read_ok");
+impl_message_type!(CounterReadOk, "read_ok");
+impl_message_type!(Add, "add");
+impl_message_type!(AddOk, "add_ok");
+impl_message_type!(Topology, "topology");
+impl_message_type!(TopologyOk, "topology_ok");
+impl_message_type!(Error, "error");
 
 #[cfg(test)]
 mod tests {
     use std::collections::HashMap;
 
-    use super::{Body, Init, Message, Topology};
+    use serde::{Deserialize, Serialize};
+    use serde_json::json;
+
+    use super::{Body, Echo, Error, ErrorCode, Extended, Init, Message, MessageType, Topology};
+
+    #[derive(Debug, Serialize, Deserialize, Eq, PartialEq)]
+    struct Ping {
+        msg_id: u64,
+        payload: String,
+    }
+
+    impl MessageType for Ping {
+        const TYPE: &'static str = "ping";
+    }
 
     #[test]
     fn test_init_msg() {
         let msg = r#"{"id":0,"src":"c0","dest":"n0","body":{"type":"init","node_id":"n0","node_ids":["n0"],"msg_id":1}}"#;
-        let got: Message = serde_json::from_str(&msg).unwrap();
+        let got: Message = serde_json::from_str(msg).unwrap();
         let want = Message {
-            src: "c0".to_string(),
-            dest: "n0".to_string(),
-            body: Body::Init(Init {
-                msg_id: 1,
-                node_id: "n0".to_string(),
-                node_ids: vec!["n0".to_string()],
-            }),
+            id: Some(0),
+            ..Message::new(
+                "c0",
+                "n0",
+                Body::Init(Init {
+                    msg_id: 1,
+                    node_id: "n0".to_string(),
+                    node_ids: vec!["n0".to_string()],
+                }),
+            )
         };
         assert_eq!(got, want);
     }
 
+    #[test]
+    fn message_keeps_id_and_unknown_fields() {
+        let msg = r#"{"id":3,"src":"c0","dest":"n0","body":{"type":"echo","echo":"hi","msg_id":1},"trace":{"span":7}}"#;
+        let got: Message = serde_json::from_str(msg).unwrap();
+        assert_eq!(got.id, Some(3));
+        assert_eq!(got.extra["trace"], json!({"span": 7}));
+        assert_eq!(
+            serde_json::to_value(&got).unwrap(),
+            serde_json::from_str::<serde_json::Value>(msg).unwrap()
+        );
+
+        let reply = got.create_response(Body::Custom(json!({"type": "echo_ok"})));
+        assert_eq!(reply.id, None);
+        assert!(reply.extra.is_empty());
+    }
+
+    #[test]
+    fn extended_keeps_unknown_body_fields() {
+        let body: Body =
+            serde_json::from_str(r#"{"type":"echo","echo":"hi","msg_id":1,"client":"x"}"#).unwrap();
+        let echo = body.decode::<Extended<Echo>>().unwrap();
+        assert_eq!(echo.message.echo, "hi");
+        assert_eq!(
+            echo.extra,
+            json!({"client": "x"}).as_object().unwrap().clone()
+        );
+        assert_eq!(Body::encode(&echo).unwrap(), body);
+
+        // the message's own fields win over extra fields of the same name
+        let mut reply = echo.with(Echo {
+            msg_id: 2,
+            echo: "ho".to_string(),
+        });
+        reply.extra.insert("echo".to_string(), json!("stale"));
+        assert_eq!(
+            serde_json::to_value(&reply).unwrap(),
+            json!({"echo": "ho", "msg_id": 2, "client": "x"})
+        );
+    }
+
+    #[test]
+    fn raw_bodies_get_their_type() {
+        assert_eq!(
+            Body::raw("echo", json!({"echo": "hi", "msg_id": 1})).unwrap(),
+            Body::Echo(Echo {
+                msg_id: 1,
+                echo: "hi".to_string(),
+            })
+        );
+        let lock = Body::raw("lock", json!({"key": 1})).unwrap();
+        assert_eq!(lock.type_name(), "lock");
+        assert_eq!(lock, Body::Custom(json!({"type": "lock", "key": 1})));
+        assert!(Body::raw("lock", json!([1])).is_err());
+    }
+
     #[test]
     fn topology_body() {
         let body = r#"{"type":"topology","topology":{"n1":["n2","n3"],"n2":["n1"],"n3":["n1"]},"msg_id":1}"#;
-        let got: Body = serde_json::from_str(&body).unwrap();
+        let got: Body = serde_json::from_str(body).unwrap();
 
         let mut topology: HashMap<String, Vec<String>> = HashMap::new();
         topology.insert(
@@ -171,4 +614,114 @@ mod tests {
         });
         assert_eq!(got, want);
     }
+
+    #[test]
+    fn error_body() {
+        let body = r#"{"type":"error","in_reply_to":5,"code":22,"text":"expected 1, had 2"}"#;
+        let got: Body = serde_json::from_str(body).unwrap();
+        let want = Body::Error(Error {
+            in_reply_to: 5,
+            code: ErrorCode::PreconditionFailed,
+            text: "expected 1, had 2".to_string(),
+        });
+        assert_eq!(got, want);
+        assert_eq!(serde_json::to_string(&want).unwrap(), body);
+    }
+
+    #[test]
+    fn error_code_round_trips_custom_codes() {
+        let got: ErrorCode = serde_json::from_str("1001").unwrap();
+        assert_eq!(got, ErrorCode::Custom(1001));
+        assert_eq!(serde_json::to_string(&got).unwrap(), "1001");
+    }
+
+    #[test]
+    fn create_error_response_replies_to_msg_id() {
+        let request = Message::new(
+            "c1",
+            "n1",
+            Body::Echo(Echo {
+                msg_id: 7,
+                echo: "hello".to_string(),
+            }),
+        );
+        let got = request
+            .create_error_response(ErrorCode::NotSupported, "echo is not supported")
+            .unwrap();
+        assert_eq!(got.src, "n1");
+        assert_eq!(got.dest, "c1");
+        assert_eq!(
+            got.body,
+            Body::Error(Error {
+                in_reply_to: 7,
+                code: ErrorCode::NotSupported,
+                text: "echo is not supported".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn unknown_types_are_kept_as_custom_body() {
+        let body = r#"{"type":"ping","msg_id":3,"payload":"hi"}"#;
+        let got: Body = serde_json::from_str(body).unwrap();
+        assert_eq!(
+            got,
+            Body::Custom(json!({"type": "ping", "msg_id": 3, "payload": "hi"}))
+        );
+        assert_eq!(got.type_name(), "ping");
+        assert_eq!(got.msg_id(), Some(3));
+        assert_eq!(
+            got.decode::<Ping>().unwrap(),
+            Ping {
+                msg_id: 3,
+                payload: "hi".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn encode_uses_builtin_variants_when_possible() {
+        let echo = Echo {
+            msg_id: 1,
+            echo: "hi".to_string(),
+        };
+        assert_eq!(Body::encode(&echo).unwrap(), Body::Echo(echo));
+
+        let ping = Ping {
+            msg_id: 2,
+            payload: "hi".to_string(),
+        };
+        let body = Body::encode(&ping).unwrap();
+        assert_eq!(body.type_name(), "ping");
+        assert_eq!(body.decode::<Ping>().unwrap(), ping);
+    }
+
+    #[test]
+    fn builtin_type_with_extra_fields_is_kept_as_custom_body() {
+        let body = r#"{"type":"read","msg_id":1,"key":"x"}"#;
+        let got: Body = serde_json::from_str(body).unwrap();
+        assert_eq!(
+            got,
+            Body::Custom(json!({"type": "read", "msg_id": 1, "key": "x"}))
+        );
+
+        let body = r#"{"type":"error","in_reply_to":1,"code":20}"#;
+        let got: Body = serde_json::from_str(body).unwrap();
+        assert!(matches!(got, Body::Error(_)));
+    }
+
+    #[test]
+    fn builtin_variants_decode_into_their_type_or_extended() {
+        let echo = Echo {
+            msg_id: 1,
+            echo: "hi".to_string(),
+        };
+        let body = Body::Echo(echo.clone());
+        assert_eq!(body.decode::<Echo>().unwrap(), echo);
+        assert_eq!(
+            body.decode::<Extended<Echo>>().unwrap(),
+            Extended::new(echo)
+        );
+        assert!(body.decode::<Ping>().is_err());
+    }
 }
@@ -1,85 +1,532 @@
-use std::{any::TypeId, collections::HashMap, sync::mpsc::Sender};
+use std::{
+    any::Any,
+    collections::HashMap,
+    fmt,
+    ops::{Deref, DerefMut},
+    panic::{self, AssertUnwindSafe},
+    sync::mpsc::Sender,
+    time::Duration,
+};
 
-use serde::de::DeserializeOwned;
+use serde_json::Value;
 
 use crate::{
-    Maelstrom,
-    messages::{
-        Body, Broadcast, BroadcastOk, Echo, EchoOk, Generate, GenerateOk, Init, InitOk, Message,
-        Read, ReadOk, Topology, TopologyOk,
-    },
+    Maelstrom, error,
+    log::MessageContext,
+    messages::{Body, Error, ErrorCode, Message, MessageType},
+    rpc::RpcOptions,
+    timer::TimerCallback,
+    warn,
 };
 
 type HandlerFn<U> =
-    dyn Fn(&Body, &mut Sender<Message>, &str, &mut Maelstrom, &mut U) + Send + 'static;
+    dyn Fn(&Message, &mut Sender<Message>, &mut Maelstrom<U>, &mut U) + Send + 'static;
+
+/// Returned by a failing handler, the router logs it and answers the request with an `error`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HandlerError {
+    pub code: ErrorCode,
+    pub text: String,
+}
+
+pub type HandlerResult = Result<(), HandlerError>;
+
+impl HandlerError {
+    pub fn new(code: ErrorCode, text: impl Into<String>) -> Self {
+        Self {
+            code,
+            text: text.into(),
+        }
+    }
+}
+
+impl fmt::Display for HandlerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}: {}", self.code, self.text)
+    }
+}
+
+impl std::error::Error for HandlerError {}
+
+/// Passes on the `error` reply of an rpc the handler depends on.
+impl From<Error> for HandlerError {
+    fn from(error: Error) -> Self {
+        Self::new(error.code, error.text)
+    }
+}
+
+/// A body that does not encode is a bug in the node, not in the request.
+impl From<serde_json::Error> for HandlerError {
+    fn from(err: serde_json::Error) -> Self {
+        Self::new(ErrorCode::Crash, format!("could not encode reply: {}", err))
+    }
+}
+
+/// Everything a handler needs besides the decoded message and the user data: who sent the
+/// message, the node state and the output. Derefs to [`Maelstrom`] for node ids, timers and ids.
+pub struct Context<'a, U> {
+    src: &'a str,
+    msg_id: Option<u64>,
+    tx: &'a mut Sender<Message>,
+    maelstrom: &'a mut Maelstrom<U>,
+}
+
+impl<'a, U> Context<'a, U> {
+    /// Node or client the message came from.
+    pub fn src(&self) -> &'a str {
+        self.src
+    }
+
+    pub fn msg_id(&self) -> Option<u64> {
+        self.msg_id
+    }
+
+    /// Sends `body` back to the sender with `in_reply_to` set to the request's `msg_id`. Messages
+    /// without `msg_id` expect no reply, replies to them are dropped.
+    pub fn reply(&mut self, body: Body) {
+        self.reply_to().reply(self.tx, self.maelstrom, body);
+    }
+
+    /// Replies with a Maelstrom `error` instead.
+    pub fn reply_error(&mut self, code: ErrorCode, text: impl Into<String>) {
+        let body = Body::Error(Error {
+            in_reply_to: 0,
+            code,
+            text: text.into(),
+        });
+        self.reply(body);
+    }
+
+    /// Who to reply to and with which `in_reply_to`, for replying later, e.g. from an rpc
+    /// callback once the answer is known.
+    pub fn reply_to(&self) -> ReplyTo {
+        ReplyTo {
+            dest: self.src.to_string(),
+            msg_id: self.msg_id,
+        }
+    }
+
+    /// Sends `body` to `dest` as it is, without expecting a reply.
+    pub fn send(&mut self, dest: &str, body: Body) {
+        let msg = self.maelstrom.create_message(dest, body);
+        self.send_message(msg);
+    }
+
+    /// Sends a message built before, e.g. one that is also kept around for retransmission.
+    pub fn send_message(&mut self, msg: Message) {
+        // only fails once the server stopped, then there is no one left to send to anyway
+        let _ = self.tx.send(msg);
+    }
+
+    /// See [`Maelstrom::rpc`], the request gets a fresh `msg_id`.
+    pub fn rpc<F>(&mut self, dest: &str, body: Body, options: RpcOptions, callback: F) -> u64
+    where
+        F: FnOnce(Result<Body, Error>, &mut Sender<Message>, &mut Maelstrom<U>, &mut U)
+            + Send
+            + 'static,
+    {
+        self.maelstrom.rpc(self.tx, dest, body, options, callback)
+    }
+
+    /// The output and the node state on their own, for APIs like [`KvClient`](crate::kv::KvClient)
+    /// that take both.
+    pub fn split(&mut self) -> (&mut Sender<Message>, &mut Maelstrom<U>) {
+        (self.tx, self.maelstrom)
+    }
+}
+
+impl<U> Deref for Context<'_, U> {
+    type Target = Maelstrom<U>;
+
+    fn deref(&self) -> &Self::Target {
+        self.maelstrom
+    }
+}
+
+impl<U> DerefMut for Context<'_, U> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.maelstrom
+    }
+}
+
+/// Sender and `msg_id` of a request, see [`Context::reply_to`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplyTo {
+    dest: String,
+    msg_id: Option<u64>,
+}
+
+impl ReplyTo {
+    pub fn reply<U>(&self, tx: &mut Sender<Message>, maelstrom: &Maelstrom<U>, mut body: Body) {
+        let Some(msg_id) = self.msg_id else {
+            warn!(
+                "Dropping reply to {}, the request has no msg_id: {:?}",
+                self.dest, body
+            );
+            return;
+        };
+        body.set_in_reply_to(msg_id);
+        let _ = tx.send(maelstrom.create_message(&self.dest, body));
+    }
+}
 
 #[derive(Default)]
 pub struct Router<U> {
-    handlers: HashMap<TypeId, Box<HandlerFn<U>>>,
-    tick: Option<Box<dyn Fn(&mut Sender<Message>, &mut Maelstrom, &mut U) + Send + 'static>>,
+    handlers: HashMap<String, Box<HandlerFn<U>>>,
+    /// Repeating timers scheduled once the server starts.
+    timers: Vec<(Duration, TimerCallback<U>)>,
 }
 
 impl<U> Router<U> {
+    /// Registers the handler for messages whose body `type` is `M::TYPE`, replacing any
+    /// previously registered handler for that type.
     pub fn on<M, F>(&mut self, handler: F)
     where
-        M: 'static + DeserializeOwned,
-        Body: Into<M> + Clone,
-        F: Fn(M, &mut Sender<Message>, &str, &mut Maelstrom, &mut U) + Send + 'static,
+        M: MessageType + 'static,
+        F: Fn(M, &mut Context<U>, &mut U) -> HandlerResult + Send + 'static,
+    {
+        self.insert(M::TYPE, move |body, context, user_data| {
+            let m = body.decode::<M>().map_err(|err| {
+                HandlerError::new(
+                    ErrorCode::MalformedRequest,
+                    format!("malformed {} body: {}", M::TYPE, err),
+                )
+            })?;
+            handler(m, context, user_data)
+        });
+    }
+
+    /// Registers a handler that gets the body as plain json, `type` included, for trying out
+    /// message types without defining a struct for them. Send replies with [`Body::raw`].
+    pub fn on_raw<F>(&mut self, type_name: &str, handler: F)
+    where
+        F: Fn(Value, &mut Context<U>, &mut U) -> HandlerResult + Send + 'static,
+    {
+        self.insert(type_name, move |body, context, user_data| {
+            handler(serde_json::to_value(body)?, context, user_data)
+        });
+    }
+
+    fn insert<F>(&mut self, type_name: &str, handler: F)
+    where
+        F: Fn(&Body, &mut Context<U>, &mut U) -> HandlerResult + Send + 'static,
     {
-        let key = TypeId::of::<M>();
-        let handler = move |body: &Body,
+        let handler = move |msg: &Message,
                             tx_output: &mut Sender<Message>,
-                            src: &str,
-                            maelstrom: &mut Maelstrom,
+                            maelstrom: &mut Maelstrom<U>,
                             user_data: &mut U| {
-            let m: M = body.clone().into();
-            handler(m, tx_output, src, maelstrom, user_data)
+            let mut context = Context {
+                src: &msg.src,
+                msg_id: msg.body.msg_id(),
+                tx: tx_output,
+                maelstrom,
+            };
+            if let Err(err) = handler(&msg.body, &mut context, user_data) {
+                warn!("Could not handle {:?}: {}", msg, err);
+                if context.msg_id.is_some() {
+                    context.reply_error(err.code, err.text);
+                }
+            }
         };
-        self.handlers.insert(key, Box::new(handler));
+        self.handlers
+            .insert(type_name.to_string(), Box::new(handler));
     }
 
-    pub fn set_tick<F>(&mut self, handler: F)
+    /// Calls `callback` every `interval` once the server runs. Timers that depend on state only
+    /// known later can be scheduled from handlers with [`Maelstrom::set_interval`].
+    pub fn every<F>(&mut self, interval: Duration, callback: F)
     where
-        F: Fn(&mut Sender<Message>, &mut Maelstrom, &mut U) + Send + 'static,
+        F: FnMut(&mut Sender<Message>, &mut Maelstrom<U>, &mut U) + Send + 'static,
     {
-        self.tick = Some(Box::new(handler));
+        self.timers.push((interval, Box::new(callback)));
     }
 
+    pub(crate) fn start_timers(&mut self, maelstrom_data: &mut Maelstrom<U>) {
+        for (interval, callback) in self.timers.drain(..) {
+            maelstrom_data
+                .timers
+                .schedule(interval, Some(interval), callback);
+        }
+    }
+
+    /// Handles everything that is due: rpc retries and timeouts and timers.
     pub fn tick(
         &self,
         tx_output: &mut Sender<Message>,
-        maelstrom_data: &mut Maelstrom,
+        maelstrom_data: &mut Maelstrom<U>,
         user_data: &mut U,
     ) {
-        if let Some(tick) = &self.tick {
-            tick(tx_output, maelstrom_data, user_data);
-        }
+        maelstrom_data.tick_rpcs(tx_output, user_data);
+        maelstrom_data.fire_timers(tx_output, user_data);
     }
 
+    /// Dispatches `msg` to its handler or pending rpc. A panic while handling it is logged and
+    /// answered with a `crash` error, the node keeps serving other messages.
     pub fn handle(
         &self,
         msg: Message,
         tx_output: &mut Sender<Message>,
-        maelstrom_data: &mut Maelstrom,
+        maelstrom_data: &mut Maelstrom<U>,
         user_data: &mut U,
     ) {
-        let key = match &msg.body {
-            Body::Init(_) => TypeId::of::<Init>(),
-            Body::InitOk(_) => TypeId::of::<InitOk>(),
-            Body::Echo(_) => TypeId::of::<Echo>(),
-            Body::EchoOk(_) => TypeId::of::<EchoOk>(),
-            Body::Generate(_) => TypeId::of::<Generate>(),
-            Body::GenerateOk(_) => TypeId::of::<GenerateOk>(),
-            Body::Topology(_) => TypeId::of::<Topology>(),
-            Body::TopologyOk(_) => TypeId::of::<TopologyOk>(),
-            Body::Broadcast(_) => TypeId::of::<Broadcast>(),
-            Body::BroadcastOk(_) => TypeId::of::<BroadcastOk>(),
-            Body::Read(_) => TypeId::of::<Read>(),
-            Body::ReadOk(_) => TypeId::of::<ReadOk>(),
+        let _context = MessageContext::enter(&msg.src, msg.body.msg_id());
+        let request = match (msg.body.msg_id(), msg.body.in_reply_to()) {
+            (Some(msg_id), None) => Some(ReplyTo {
+                dest: msg.src.clone(),
+                msg_id: Some(msg_id),
+            }),
+            _ => None,
         };
+        let panicked = catch_panic("handler", || {
+            self.dispatch(msg, tx_output, maelstrom_data, user_data)
+        });
+        if let Some(text) = panicked
+            && let Some(request) = request
+        {
+            let body = Body::Error(Error {
+                in_reply_to: 0,
+                code: ErrorCode::Crash,
+                text,
+            });
+            request.reply(tx_output, maelstrom_data, body);
+        }
+    }
 
-        if let Some(handler) = self.handlers.get(&key) {
-            handler(&msg.body, tx_output, &msg.src, maelstrom_data, user_data);
+    fn dispatch(
+        &self,
+        msg: Message,
+        tx_output: &mut Sender<Message>,
+        maelstrom_data: &mut Maelstrom<U>,
+        user_data: &mut U,
+    ) {
+        let Some(msg) = maelstrom_data.complete_rpc(msg, tx_output, user_data) else {
+            return;
+        };
+
+        if let Some(handler) = self.handlers.get(msg.body.type_name()) {
+            handler(&msg, tx_output, maelstrom_data, user_data);
+        } else if msg.body.in_reply_to().is_some() {
+            warn!("Unhandled reply from {}: {:?}", msg.src, msg.body);
+        } else {
+            warn!("Unhandled request from {}: {:?}", msg.src, msg.body);
+            let text = "no handler registered for this message type";
+            if let Some(reply) = msg.create_error_response(ErrorCode::NotSupported, text) {
+                let _ = tx_output.send(reply);
+            }
         }
     }
 }
+
+/// Runs `callback` and logs a panic instead of letting it unwind into the server loop, so one
+/// failing handler, timer or rpc callback doesn't stop the node. Returns the logged text.
+pub(crate) fn catch_panic<F: FnOnce()>(what: &str, callback: F) -> Option<String> {
+    let payload = panic::catch_unwind(AssertUnwindSafe(callback)).err()?;
+    let text = format!("{} panicked: {}", what, panic_message(&*payload));
+    error!("{}", text);
+    Some(text)
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "unknown panic"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use serde::{Deserialize, Serialize};
+    use serde_json::json;
+
+    use crate::{
+        messages::{Body, ErrorCode, MessageType},
+        rpc::RpcOptions,
+        testing,
+        workloads::init::create_router,
+    };
+
+    use super::HandlerError;
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct Ping {
+        msg_id: u64,
+        count: u64,
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct Pong {
+        in_reply_to: u64,
+        count: u64,
+    }
+
+    /// Sent with or without `msg_id`.
+    #[derive(Debug, Serialize, Deserialize)]
+    struct Note {
+        count: u64,
+    }
+
+    impl MessageType for Note {
+        const TYPE: &'static str = "note";
+    }
+
+    impl MessageType for Ping {
+        const TYPE: &'static str = "ping";
+    }
+
+    impl MessageType for Pong {
+        const TYPE: &'static str = "pong";
+    }
+
+    #[test]
+    fn should_dispatch_custom_message_types() {
+        let mut router = create_router::<()>();
+        router.on(|ping: Ping, ctx, _| {
+            let body = Body::encode(&Pong {
+                in_reply_to: ping.msg_id,
+                count: ping.count + 1,
+            })?;
+            ctx.reply(body);
+            Ok(())
+        });
+        testing::TestServer::from_router(router)
+            .send_str(r#"{"src":"c1","dest":"n1","body":{"type":"ping","msg_id":1,"count":41}}"#)
+            .assert_msg_received_default_timeout(|msg| {
+                msg.body.type_name() == "pong"
+                    && msg.body.in_reply_to() == Some(1)
+                    && msg.body.decode::<Pong>().unwrap().count == 42
+            });
+    }
+
+    #[test]
+    fn should_dispatch_raw_bodies_by_type_string() {
+        let mut router = create_router::<()>();
+        router.on_raw("lock", |body, ctx, _| {
+            let key = body["key"].as_str().unwrap_or_default().to_string();
+            ctx.reply(Body::raw(
+                "lock_ok",
+                json!({"key": key, "holder": ctx.src()}),
+            )?);
+            Ok(())
+        });
+        testing::TestServer::from_router(router)
+            .send_str(r#"{"src":"c1","dest":"n1","body":{"type":"lock","key":"a","msg_id":3}}"#)
+            .assert_msg_received_default_timeout(|msg| {
+                msg.body.type_name() == "lock_ok"
+                    && msg.body.in_reply_to() == Some(3)
+                    && matches!(&msg.body, Body::Custom(body) if body["key"] == "a" && body["holder"] == "c1")
+            });
+    }
+
+    #[test]
+    fn should_fill_in_reply_to_and_drop_replies_to_messages_without_msg_id() {
+        let mut router = create_router::<()>();
+        router.on(|note: Note, ctx, _| {
+            ctx.reply(Body::Custom(json!({"type": "noted", "count": note.count})));
+            Ok(())
+        });
+        let server = testing::TestServer::from_router(router)
+            .send_str(r#"{"src":"c1","dest":"n1","body":{"type":"note","msg_id":1,"count":7}}"#)
+            .send_str(r#"{"src":"c1","dest":"n1","body":{"type":"note","count":8}}"#)
+            .assert_msg_received_default_timeout(|msg| {
+                msg.dest == "c1" && msg.body.in_reply_to() == Some(1)
+            })
+            .wait_for_messages();
+        assert_eq!(server.get_messages().len(), 1);
+    }
+
+    #[test]
+    fn should_reply_handler_errors_and_survive_panics() {
+        let mut router = create_router::<()>();
+        router.on(|ping: Ping, ctx, _| match ping.count {
+            0 => Err(HandlerError::new(ErrorCode::Abort, "count must not be 0")),
+            1 => panic!("count 1"),
+            count => {
+                ctx.reply(Body::encode(&Pong {
+                    in_reply_to: ping.msg_id,
+                    count,
+                })?);
+                Ok(())
+            }
+        });
+        testing::TestServer::from_router(router)
+            .send_str(r#"{"src":"c1","dest":"n1","body":{"type":"ping","msg_id":1,"count":0}}"#)
+            .assert_msg_received_default_timeout(|msg| {
+                matches!(&msg.body, Body::Error(error)
+                    if error.code == ErrorCode::Abort && error.in_reply_to == 1 && error.text == "count must not be 0")
+            })
+            .send_str(r#"{"src":"c1","dest":"n1","body":{"type":"ping","msg_id":2,"count":1}}"#)
+            .assert_msg_received_default_timeout(|msg| {
+                matches!(&msg.body, Body::Error(error)
+                    if error.code == ErrorCode::Crash && error.in_reply_to == 2 && error.text.contains("count 1"))
+            })
+            .send_str(r#"{"src":"c1","dest":"n1","body":{"type":"ping","msg_id":3,"count":2}}"#)
+            .assert_msg_received_default_timeout(|msg| msg.body.in_reply_to() == Some(3));
+    }
+
+    #[test]
+    fn should_survive_panics_in_timer_and_rpc_callbacks() {
+        let mut router = create_router::<()>();
+        router.every(Duration::from_millis(10), |_, _, _| panic!("timer"));
+        router.on(|ping: Ping, ctx, _| {
+            let options = RpcOptions::default().with_timeout(Duration::from_millis(20));
+            let body = Body::encode(&Note { count: ping.count })?;
+            ctx.rpc("n9", body, options, |_, _, _, _| panic!("rpc callback"));
+            // answered after the rpc timed out and the timer panicked a few times
+            let reply_to = ctx.reply_to();
+            let body = Body::encode(&Pong {
+                in_reply_to: ping.msg_id,
+                count: ping.count,
+            })?;
+            ctx.set_timeout(Duration::from_millis(100), move |tx, maelstrom, _| {
+                reply_to.reply(tx, maelstrom, body)
+            });
+            Ok(())
+        });
+        testing::TestServer::from_router(router)
+            .send_str(r#"{"src":"c1","dest":"n1","body":{"type":"ping","msg_id":1,"count":1}}"#)
+            .assert_msg_received_default_timeout(|msg| msg.body.in_reply_to() == Some(1))
+            .send_str(r#"{"src":"c1","dest":"n1","body":{"type":"ping","msg_id":2,"count":2}}"#)
+            .assert_msg_received_default_timeout(|msg| msg.body.in_reply_to() == Some(2));
+    }
+
+    #[test]
+    fn should_reply_malformed_request_when_body_does_not_decode() {
+        let mut router = create_router::<()>();
+        router.on(|_: Ping, _, _| Ok(()));
+        testing::TestServer::from_router(router)
+            .send_str(r#"{"src":"c1","dest":"n1","body":{"type":"ping","msg_id":1}}"#)
+            .assert_msg_received_default_timeout(|msg| {
+                matches!(&msg.body, Body::Error(error) if error.code == ErrorCode::MalformedRequest)
+            });
+    }
+
+    #[test]
+    fn should_reply_not_supported_for_unhandled_requests() {
+        let router = create_router::<()>();
+        testing::TestServer::from_router(router)
+            .send_str(r#"{"src":"c1","dest":"n1","body":{"type":"echo","msg_id":4,"echo":"hi"}}"#)
+            .assert_msg_received_default_timeout(|msg| {
+                if let Body::Error(error) = &msg.body {
+                    error.code == ErrorCode::NotSupported && error.in_reply_to == 4
+                } else {
+                    false
+                }
+            });
+    }
+
+    #[test]
+    fn should_not_reply_to_unhandled_replies() {
+        let router = create_router::<()>();
+        let server = testing::TestServer::from_router(router)
+            .send_str(
+                r#"{"src":"n2","dest":"n1","body":{"type":"echo_ok","in_reply_to":4,"echo":"hi"}}"#,
+            )
+            .wait_for_messages();
+        assert!(server.get_messages().is_empty());
+    }
+}
@@ -0,0 +1,253 @@
+use std::{
+    fmt::{self, Debug},
+    sync::mpsc::Sender,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    Maelstrom,
+    messages::{Body, Error, ErrorCode, Message},
+    router::catch_panic,
+};
+
+/// Called exactly once per rpc, with the reply body or with the `error` reply. A timeout is
+/// reported as an [`ErrorCode::Timeout`] error generated locally.
+pub type RpcCallback<U> =
+    Box<dyn FnOnce(Result<Body, Error>, &mut Sender<Message>, &mut Maelstrom<U>, &mut U) + Send>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RpcOptions {
+    /// Time after which the rpc is given up and the callback receives a timeout error.
+    pub timeout: Duration,
+    /// Resend the request with the same `msg_id` after this interval until a reply arrives.
+    pub retry_interval: Option<Duration>,
+}
+
+impl Default for RpcOptions {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(1),
+            retry_interval: None,
+        }
+    }
+}
+
+impl RpcOptions {
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn with_retry_interval(mut self, retry_interval: Duration) -> Self {
+        self.retry_interval = Some(retry_interval);
+        self
+    }
+}
+
+pub(crate) struct PendingRpc<U> {
+    request: Message,
+    options: RpcOptions,
+    started: Instant,
+    last_sent: Instant,
+    callback: RpcCallback<U>,
+}
+
+impl<U> Debug for PendingRpc<U> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PendingRpc")
+            .field("request", &self.request)
+            .field("options", &self.options)
+            .field("started", &self.started)
+            .field("last_sent", &self.last_sent)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<U> Maelstrom<U> {
+    /// Sends `body` to `dest` with a fresh `msg_id` and registers `callback` for the reply
+    /// with the matching `in_reply_to`. Returns the `msg_id` used for the request.
+    pub fn rpc<F>(
+        &mut self,
+        tx: &mut Sender<Message>,
+        dest: &str,
+        mut body: Body,
+        options: RpcOptions,
+        callback: F,
+    ) -> u64
+    where
+        F: FnOnce(Result<Body, Error>, &mut Sender<Message>, &mut Maelstrom<U>, &mut U)
+            + Send
+            + 'static,
+    {
+        let msg_id = self.generate_id();
+        body.set_msg_id(msg_id);
+        let request = self.create_message(dest, body);
+        // only fails once the server stopped, then the rpc simply times out
+        let _ = tx.send(request.clone());
+
+        let now = Instant::now();
+        self.rpcs.insert(
+            msg_id,
+            PendingRpc {
+                request,
+                options,
+                started: now,
+                last_sent: now,
+                callback: Box::new(callback),
+            },
+        );
+        msg_id
+    }
+
+    /// Completes the rpc the message replies to, returns the message back if it is not a reply
+    /// to a pending rpc.
+    pub(crate) fn complete_rpc(
+        &mut self,
+        msg: Message,
+        tx: &mut Sender<Message>,
+        user_data: &mut U,
+    ) -> Option<Message> {
+        let Some(pending) = msg
+            .body
+            .in_reply_to()
+            .and_then(|in_reply_to| self.rpcs.remove(&in_reply_to))
+        else {
+            return Some(msg);
+        };
+
+        let result = match msg.body {
+            Body::Error(error) => Err(error),
+            body => Ok(body),
+        };
+        catch_panic("rpc callback", || {
+            (pending.callback)(result, tx, self, user_data)
+        });
+        None
+    }
+
+    pub(crate) fn next_rpc_deadline(&self) -> Option<Instant> {
+        self.rpcs
+            .values()
+            .flat_map(|pending| {
+                let retry = pending
+                    .options
+                    .retry_interval
+                    .map(|retry_interval| pending.last_sent + retry_interval);
+                [Some(pending.started + pending.options.timeout), retry]
+            })
+            .flatten()
+            .min()
+    }
+
+    /// Resends requests whose retry interval elapsed and fails those past their timeout.
+    pub(crate) fn tick_rpcs(&mut self, tx: &mut Sender<Message>, user_data: &mut U) {
+        let now = Instant::now();
+        let timed_out: Vec<u64> = self
+            .rpcs
+            .iter()
+            .filter(|(_, pending)| pending.options.timeout <= now - pending.started)
+            .map(|(msg_id, _)| *msg_id)
+            .collect();
+
+        for pending in self.rpcs.values_mut() {
+            if let Some(retry_interval) = pending.options.retry_interval
+                && retry_interval <= now - pending.last_sent
+            {
+                pending.last_sent = now;
+                let _ = tx.send(pending.request.clone());
+            }
+        }
+
+        for msg_id in timed_out {
+            if let Some(pending) = self.rpcs.remove(&msg_id) {
+                let error = Error {
+                    in_reply_to: msg_id,
+                    code: ErrorCode::Timeout,
+                    text: format!(
+                        "no reply from {} after {:?}",
+                        pending.request.dest, pending.options.timeout
+                    ),
+                };
+                catch_panic("rpc callback", || {
+                    (pending.callback)(Err(error), tx, self, user_data)
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{
+        messages::{Body, Echo, EchoOk, ErrorCode},
+        testing,
+        workloads::init::create_router,
+    };
+
+    use super::RpcOptions;
+
+    fn create_forwarding_router(options: RpcOptions) -> crate::router::Router<()> {
+        let mut router = create_router::<()>();
+        router.on(move |echo: Echo, ctx, _| {
+            let reply_to = ctx.reply_to();
+            let in_reply_to = echo.msg_id;
+            let body = Body::Echo(echo.clone());
+            ctx.rpc("n2", body, options, move |result, tx, maelstrom, _| {
+                let echo = match result {
+                    Ok(Body::EchoOk(echo_ok)) => echo_ok.echo,
+                    Ok(body) => format!("unexpected {:?}", body),
+                    Err(error) => format!("{:?}", error.code),
+                };
+                let body = Body::EchoOk(EchoOk {
+                    msg_id: None,
+                    in_reply_to,
+                    echo,
+                });
+                reply_to.reply(tx, maelstrom, body);
+            });
+            Ok(())
+        });
+        router
+    }
+
+    #[test]
+    fn should_invoke_callback_with_reply() {
+        let router = create_forwarding_router(RpcOptions::default());
+        testing::TestServer::from_router(router)
+            .send_str(r#"{"src":"c1","dest":"n1","body":{"type":"echo","msg_id":1,"echo":"ping"}}"#)
+            .assert_msg_received_default_timeout(|msg| {
+                msg.dest == "n2" && msg.body.msg_id() == Some(0)
+            })
+            .send_str(
+                r#"{"src":"n2","dest":"n1","body":{"type":"echo_ok","in_reply_to":0,"echo":"pong"}}"#,
+            )
+            .assert_msg_received_default_timeout(|msg| {
+                matches!(&msg.body, Body::EchoOk(echo_ok) if msg.dest == "c1" && echo_ok.echo == "pong")
+            });
+    }
+
+    #[test]
+    fn should_retry_and_report_timeout() {
+        let options = RpcOptions::default()
+            .with_timeout(Duration::from_millis(300))
+            .with_retry_interval(Duration::from_millis(50));
+        let router = create_forwarding_router(options);
+        let server = testing::TestServer::from_router(router)
+            .send_str(r#"{"src":"c1","dest":"n1","body":{"type":"echo","msg_id":1,"echo":"ping"}}"#)
+            .assert_msg_received_timeout(
+                |msg| {
+                    matches!(&msg.body, Body::EchoOk(echo_ok) if echo_ok.echo == format!("{:?}", ErrorCode::Timeout))
+                },
+                Duration::from_secs(1),
+            );
+
+        let attempts = server
+            .get_messages()
+            .iter()
+            .filter(|msg| msg.dest == "n2")
+            .count();
+        assert!(attempts > 1, "expected retries, got {} attempts", attempts);
+    }
+}
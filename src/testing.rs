@@ -1,4 +1,5 @@
 use std::{
+    collections::{HashMap, HashSet},
     fmt::Debug,
     io::{BufReader, Cursor, Read, Write},
     sync::mpsc::{self, Receiver, Sender},
@@ -6,37 +7,66 @@ use std::{
     time::{Duration, Instant},
 };
 
-use crate::{Server, messages::Message, router::Router};
+use serde_json::Value;
+
+use crate::{
+    Server,
+    kv::{KvCas, KvCasOk, KvRead, KvReadOk, KvService, KvWrite, KvWriteOk},
+    messages::{Body, ErrorCode, Init, Message, MessageType},
+    rand::{SplitMix64, fnv1a},
+    router::Router,
+};
 
 pub struct TestServer {
     output_msgs: Vec<Message>,
+    kv_stores: HashMap<String, KvStore>,
     input_sender: Option<Sender<String>>,
     output_receiver: Receiver<String>,
+    /// How long to wait for an expected message, and how long the node has to stay quiet for
+    /// [`TestServer::wait_for_messages`] to return. With 20ms replies were missed whenever
+    /// `cargo test` ran the suite on all cores. Assertions return as soon as the message arrives.
     default_timeout: Duration,
 }
 
 impl TestServer {
     pub fn from_router<U>(router: Router<U>) -> TestServer
     where
-        U: Default + Debug + 'static,
+        U: Default + Debug + Send + 'static,
+    {
+        Self::from_router_with_data(router, U::default())
+    }
+
+    pub fn from_router_with_data<U>(router: Router<U>, user_data: U) -> TestServer
+    where
+        U: Debug + Send + 'static,
     {
         let (input_sender, input_receiver) = ReceiverRead::new();
         let (output_sender, output_receiver) = SenderWrite::new();
 
         thread::spawn(move || {
             let reader = BufReader::new(input_receiver);
-            let mut server = Server::new(reader, output_sender, router, U::default());
-            server.serve();
+            let mut server = Server::new(reader, output_sender, router, user_data);
+            // fails with a broken pipe once the test dropped the output receiver
+            let _ = server.serve();
         });
 
         TestServer {
             output_msgs: Vec::new(),
+            kv_stores: HashMap::new(),
             input_sender: Some(input_sender),
             output_receiver,
-            default_timeout: Duration::from_millis(20),
+            default_timeout: Duration::from_millis(200),
         }
     }
 
+    /// Answers messages sent to `service` with an in-process [`KvStore`], the requests and
+    /// replies still show up in the received messages.
+    pub fn with_kv_store(mut self, service: KvService) -> Self {
+        self.kv_stores
+            .insert(service.node_id().to_string(), KvStore::default());
+        self
+    }
+
     pub fn send_str(mut self, raw_msg: &str) -> Self {
         let msg = raw_msg.to_string() + "\n";
         let sender = self.input_sender.expect("Input closed");
@@ -65,16 +95,14 @@ impl TestServer {
 
         let start = Instant::now();
         while start.elapsed() < timeout {
-            match self.output_receiver.recv_timeout(timeout - start.elapsed()) {
-                Ok(msg_str) => {
-                    let msg = parse_raw_message(msg_str);
-                    let found = predicate(&msg);
-                    self.output_msgs.push(msg);
-                    if found {
-                        return self;
-                    }
+            let remaining = timeout.saturating_sub(start.elapsed());
+            if let Ok(msg_str) = self.output_receiver.recv_timeout(remaining) {
+                let msg = parse_raw_message(msg_str);
+                let found = predicate(&msg);
+                self.record(msg);
+                if found {
+                    return self;
                 }
-                Err(_) => {}
             }
         }
 
@@ -85,23 +113,443 @@ impl TestServer {
     }
 
     pub fn wait_for_messages(mut self) -> Self {
-        loop {
-            match self.output_receiver.recv_timeout(self.default_timeout) {
-                Ok(msg_str) => {
-                    let msg = parse_raw_message(msg_str);
-                    self.output_msgs.push(msg);
-                }
-                Err(_) => break,
-            }
+        while let Ok(msg_str) = self.output_receiver.recv_timeout(self.default_timeout) {
+            let msg = parse_raw_message(msg_str);
+            self.record(msg);
+        }
+        self
+    }
+
+    /// Collects everything the node sends during `duration`, also if it never goes quiet.
+    pub fn run_for(mut self, duration: Duration) -> Self {
+        let deadline = Instant::now() + duration;
+        while let Ok(msg_str) = self
+            .output_receiver
+            .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+        {
+            let msg = parse_raw_message(msg_str);
+            self.record(msg);
         }
         self
     }
 
+    fn record(&mut self, msg: Message) {
+        if let Some(kv_store) = self.kv_stores.get_mut(&msg.dest)
+            && let Some(reply) = kv_store.handle(&msg)
+        {
+            let raw_reply = serde_json::to_string(&reply).unwrap() + "\n";
+            if let Some(sender) = &self.input_sender {
+                sender.send(raw_reply).expect("Receiver is disconnected...");
+            }
+            self.output_msgs.push(msg);
+            self.output_msgs.push(reply);
+        } else {
+            self.output_msgs.push(msg);
+        }
+    }
+
     pub fn get_messages(&self) -> &Vec<Message> {
         &self.output_msgs
     }
 }
 
+/// Network faults for messages between nodes of a [`TestCluster`], clients and kv stores are
+/// always reachable. Whether a message is lost, duplicated and how long it is delayed only
+/// depends on the seed, the message and how often it was sent before, not on the order the node
+/// threads send in. Timers and thread scheduling still vary between runs, so tests should wait
+/// for convergence instead of expecting an exact run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Faults {
+    /// Chance that a message is lost.
+    pub loss: f64,
+    /// Chance that a message is delivered twice.
+    pub duplication: f64,
+    /// Every message is delayed by a random duration between these bounds.
+    pub latency: (Duration, Duration),
+    pub seed: u64,
+}
+
+impl Default for Faults {
+    fn default() -> Self {
+        Self {
+            loss: 0.0,
+            duplication: 0.0,
+            latency: (Duration::ZERO, Duration::ZERO),
+            seed: 0,
+        }
+    }
+}
+
+impl Faults {
+    pub fn with_loss(mut self, loss: f64) -> Self {
+        self.loss = loss;
+        self
+    }
+
+    pub fn with_duplication(mut self, duplication: f64) -> Self {
+        self.duplication = duplication;
+        self
+    }
+
+    /// Messages overtake each other whenever `max` is above `min`.
+    pub fn with_latency(mut self, min: Duration, max: Duration) -> Self {
+        self.latency = (min, max);
+        self
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+}
+
+/// Decides the fate of every message between nodes, see [`Faults`].
+struct FaultInjector {
+    faults: Faults,
+    /// Sends by serialized message, so retransmissions get decisions of their own.
+    sent: HashMap<String, u64>,
+}
+
+impl FaultInjector {
+    fn new(faults: Faults) -> Self {
+        Self {
+            faults,
+            sent: HashMap::new(),
+        }
+    }
+
+    /// Delays of the copies of `msg` to deliver, none if it's lost.
+    fn delays(&mut self, msg: &Message) -> Vec<Duration> {
+        let raw_msg = serde_json::to_string(msg).unwrap();
+        let sent = self.sent.entry(raw_msg.clone()).or_default();
+        *sent += 1;
+        let key = format!("{} {} {}", self.faults.seed, sent, raw_msg);
+        let mut rng = SplitMix64::from_seed(fnv1a(key.as_bytes()));
+
+        if unit(&mut rng) < self.faults.loss {
+            return Vec::new();
+        }
+        let copies = if unit(&mut rng) < self.faults.duplication {
+            2
+        } else {
+            1
+        };
+        let (min, max) = self.faults.latency;
+        (0..copies)
+            .map(|_| min + max.saturating_sub(min).mul_f64(unit(&mut rng)))
+            .collect()
+    }
+}
+
+/// Uniform in `[0, 1)`.
+fn unit(rng: &mut SplitMix64) -> f64 {
+    (rng.next() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Runs one [`Server`] per node in this process and passes their messages on by `dest`. The test
+/// plays the clients, messages to anyone but the nodes and kv stores are collected for it.
+pub struct TestCluster {
+    node_ids: Vec<String>,
+    inputs: HashMap<String, Sender<String>>,
+    /// Output of all nodes.
+    output_receiver: Receiver<String>,
+    kv_stores: HashMap<String, KvStore>,
+    client_msgs: Vec<Message>,
+    next_msg_id: u64,
+    default_timeout: Duration,
+    faults: FaultInjector,
+    /// Pairs of node sets that can't reach each other.
+    partitions: Vec<(HashSet<String>, HashSet<String>)>,
+    /// Messages between nodes by delivery time.
+    in_flight: Vec<(Instant, Message)>,
+    dropped: usize,
+}
+
+impl TestCluster {
+    /// Starts the nodes `n0`, `n1`, ... and waits until all of them answered `init`.
+    pub fn new<U, F>(node_count: usize, create_router: F) -> TestCluster
+    where
+        U: Default + Debug + Send + 'static,
+        F: Fn() -> Router<U>,
+    {
+        Self::with_data(node_count, || (create_router(), U::default()))
+    }
+
+    pub fn with_data<U, F>(node_count: usize, create_node: F) -> TestCluster
+    where
+        U: Debug + Send + 'static,
+        F: Fn() -> (Router<U>, U),
+    {
+        let (output_sender, output_receiver) = mpsc::channel();
+        let node_ids: Vec<String> = (0..node_count).map(|node| format!("n{}", node)).collect();
+        let mut inputs = HashMap::new();
+        for node_id in &node_ids {
+            let (input_sender, input_receiver) = ReceiverRead::new();
+            let writer = SenderWrite {
+                sender: output_sender.clone(),
+                buffer: vec![],
+            };
+            let (router, user_data) = create_node();
+            thread::spawn(move || {
+                let reader = BufReader::new(input_receiver);
+                let mut server = Server::new(reader, writer, router, user_data);
+                let _ = server.serve();
+            });
+            inputs.insert(node_id.clone(), input_sender);
+        }
+
+        let mut cluster = TestCluster {
+            node_ids,
+            inputs,
+            output_receiver,
+            kv_stores: HashMap::new(),
+            client_msgs: Vec::new(),
+            next_msg_id: 0,
+            default_timeout: Duration::from_secs(1),
+            faults: FaultInjector::new(Faults::default()),
+            partitions: Vec::new(),
+            in_flight: Vec::new(),
+            dropped: 0,
+        };
+        for node_id in cluster.node_ids.clone() {
+            let init = Body::Init(Init {
+                msg_id: 0,
+                node_id: node_id.clone(),
+                node_ids: cluster.node_ids.clone(),
+            });
+            cluster.request(&node_id, init);
+        }
+        cluster
+    }
+
+    /// Answers messages sent to `service` with an in-process [`KvStore`].
+    pub fn with_kv_store(mut self, service: KvService) -> Self {
+        self.kv_stores
+            .insert(service.node_id().to_string(), KvStore::default());
+        self
+    }
+
+    pub fn with_faults(mut self, faults: Faults) -> Self {
+        self.faults = FaultInjector::new(faults);
+        self
+    }
+
+    /// Drops all messages between `nodes` and `others` until [`TestCluster::heal`], including
+    /// the ones already in flight.
+    pub fn partition(&mut self, nodes: &[&str], others: &[&str]) {
+        let side = |nodes: &[&str]| nodes.iter().map(|node| node.to_string()).collect();
+        self.partitions.push((side(nodes), side(others)));
+    }
+
+    /// Removes all partitions.
+    pub fn heal(&mut self) {
+        self.partitions.clear();
+    }
+
+    pub fn node_ids(&self) -> &[String] {
+        &self.node_ids
+    }
+
+    /// Messages between nodes lost to faults or partitions so far.
+    pub fn dropped_messages(&self) -> usize {
+        self.dropped
+    }
+
+    /// Sends `body` from client `c1` to `dest` with a fresh `msg_id`, which is returned.
+    pub fn send(&mut self, dest: &str, mut body: Body) -> u64 {
+        let msg_id = self.next_msg_id;
+        self.next_msg_id += 1;
+        body.set_msg_id(msg_id);
+        self.deliver(Message::new("c1", dest, body));
+        msg_id
+    }
+
+    /// Sends `body` to `dest` and runs the cluster until the reply arrived.
+    pub fn request(&mut self, dest: &str, body: Body) -> Message {
+        let msg_id = self.send(dest, body);
+        let is_reply = |msg: &Message| msg.dest == "c1" && msg.body.in_reply_to() == Some(msg_id);
+        let deadline = Instant::now() + self.default_timeout;
+        loop {
+            if let Some(reply) = self.client_msgs.iter().rev().find(|msg| is_reply(msg)) {
+                return reply.clone();
+            }
+            if Instant::now() >= deadline {
+                panic!(
+                    "No reply from {} to {} before timeout, client messages:\n{:#?}",
+                    dest, msg_id, self.client_msgs
+                );
+            }
+            self.step(deadline);
+        }
+    }
+
+    /// Passes messages between the nodes for `duration`.
+    pub fn run_for(&mut self, duration: Duration) {
+        let deadline = Instant::now() + duration;
+        while Instant::now() < deadline {
+            self.step(deadline);
+        }
+    }
+
+    /// Runs the cluster until `condition` holds, e.g. until every node read all values. Panics
+    /// if it still does not hold after `timeout`.
+    pub fn run_until<F>(&mut self, timeout: Duration, mut condition: F)
+    where
+        F: FnMut(&mut TestCluster) -> bool,
+    {
+        let deadline = Instant::now() + timeout;
+        while !condition(self) {
+            if Instant::now() >= deadline {
+                panic!("Cluster did not converge within {:?}", timeout);
+            }
+            self.run_for(Duration::from_millis(50));
+        }
+    }
+
+    /// Delivers the messages that are due or routes one message the nodes sent, waits until
+    /// `deadline` at most.
+    fn step(&mut self, deadline: Instant) {
+        if self.deliver_due() {
+            return;
+        }
+        let wake_up = match self.in_flight.first() {
+            Some((delivery, _)) => deadline.min(*delivery),
+            None => deadline,
+        };
+        let timeout = wake_up.saturating_duration_since(Instant::now());
+        if let Ok(raw_msg) = self.output_receiver.recv_timeout(timeout) {
+            self.route(parse_raw_message(raw_msg));
+        }
+    }
+
+    fn deliver_due(&mut self) -> bool {
+        let now = Instant::now();
+        let due = self
+            .in_flight
+            .partition_point(|(delivery, _)| *delivery <= now);
+        let msgs: Vec<_> = self.in_flight.drain(..due).collect();
+        for (_, msg) in msgs {
+            self.deliver(msg);
+        }
+        due > 0
+    }
+
+    /// Applies the faults to messages between nodes and holds them back until their delivery.
+    fn route(&mut self, msg: Message) {
+        if !self.inputs.contains_key(&msg.src) || !self.inputs.contains_key(&msg.dest) {
+            self.deliver(msg);
+            return;
+        }
+        let delays = self.faults.delays(&msg);
+        if delays.is_empty() {
+            self.dropped += 1;
+        }
+        for delay in delays {
+            let delivery = Instant::now() + delay;
+            let index = self
+                .in_flight
+                .partition_point(|(other, _)| *other <= delivery);
+            self.in_flight.insert(index, (delivery, msg.clone()));
+        }
+    }
+
+    fn is_partitioned(&self, src: &str, dest: &str) -> bool {
+        self.partitions.iter().any(|(nodes, others)| {
+            (nodes.contains(src) && others.contains(dest))
+                || (others.contains(src) && nodes.contains(dest))
+        })
+    }
+
+    fn deliver(&mut self, msg: Message) {
+        if self.is_partitioned(&msg.src, &msg.dest) {
+            self.dropped += 1;
+        } else if let Some(input) = self.inputs.get(&msg.dest) {
+            let raw_msg = serde_json::to_string(&msg).unwrap() + "\n";
+            // a node that stopped serving just doesn't get messages anymore
+            let _ = input.send(raw_msg);
+        } else if let Some(kv_store) = self.kv_stores.get_mut(&msg.dest) {
+            if let Some(reply) = kv_store.handle(&msg) {
+                self.deliver(reply);
+            }
+        } else {
+            self.client_msgs.push(msg);
+        }
+    }
+}
+
+/// In-process stand-in for the Maelstrom key/value services, answering `read`, `write` and
+/// `cas` requests like `lin-kv` would. Used to run kv backed workloads without Maelstrom.
+#[derive(Debug, Default, Clone)]
+pub struct KvStore {
+    values: HashMap<String, Value>,
+}
+
+impl KvStore {
+    pub fn get(&self, key: &Value) -> Option<&Value> {
+        self.values.get(&key.to_string())
+    }
+
+    /// Applies the request and returns the reply, `None` if the message is not a request.
+    pub fn handle(&mut self, request: &Message) -> Option<Message> {
+        let msg_id = request.body.msg_id()?;
+        let result = match request.body.type_name() {
+            KvRead::TYPE => request.body.decode().map(|read| self.read(read)),
+            KvWrite::TYPE => request.body.decode().map(|write| self.write(write)),
+            KvCas::TYPE => request.body.decode().map(|cas| self.cas(cas)),
+            _ => {
+                let text = format!("{} is not supported", request.body.type_name());
+                return request.create_error_response(ErrorCode::NotSupported, text);
+            }
+        };
+
+        match result {
+            Ok(Ok(mut body)) => {
+                body.set_in_reply_to(msg_id);
+                Some(request.create_response(body))
+            }
+            Ok(Err((code, text))) => request.create_error_response(code, text),
+            Err(err) => request.create_error_response(ErrorCode::MalformedRequest, err.to_string()),
+        }
+    }
+
+    fn read(&mut self, read: KvRead) -> Result<Body, (ErrorCode, String)> {
+        match self.get(&read.key) {
+            Some(value) => Ok(Body::encode(&KvReadOk {
+                value: value.clone(),
+            })
+            .unwrap()),
+            None => Err(key_does_not_exist(&read.key)),
+        }
+    }
+
+    fn write(&mut self, write: KvWrite) -> Result<Body, (ErrorCode, String)> {
+        self.values.insert(write.key.to_string(), write.value);
+        Ok(Body::encode(&KvWriteOk {}).unwrap())
+    }
+
+    fn cas(&mut self, cas: KvCas) -> Result<Body, (ErrorCode, String)> {
+        match self.values.get_mut(&cas.key.to_string()) {
+            Some(current) if *current == cas.from => *current = cas.to,
+            Some(current) => {
+                let text = format!("expected {}, but had {}", cas.from, current);
+                return Err((ErrorCode::PreconditionFailed, text));
+            }
+            None if cas.create_if_not_exists => {
+                self.values.insert(cas.key.to_string(), cas.to);
+            }
+            None => return Err(key_does_not_exist(&cas.key)),
+        }
+        Ok(Body::encode(&KvCasOk {}).unwrap())
+    }
+}
+
+fn key_does_not_exist(key: &Value) -> (ErrorCode, String) {
+    (
+        ErrorCode::KeyDoesNotExist,
+        format!("key {} does not exist", key),
+    )
+}
+
 fn parse_raw_message(raw_msg: String) -> Message {
     match serde_json::from_str(&raw_msg) {
         Ok(msg) => msg,
@@ -158,14 +606,15 @@ impl SenderWrite {
 
 impl Write for SenderWrite {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-        self.buffer.extend_from_slice(&buf);
+        self.buffer.extend_from_slice(buf);
         let index = self.buffer.iter().position(|c| c == &b'\n');
         if let Some(index) = index {
             let rem = self.buffer.split_off(index + 1);
             self.buffer.pop(); // remove b'\n'
+            // like stdout once the reading side went away
             self.sender
                 .send(String::from_utf8(self.buffer.clone()).unwrap())
-                .expect("Receiver disconnected...");
+                .map_err(|_| std::io::Error::from(std::io::ErrorKind::BrokenPipe))?;
             self.buffer = rem;
         }
         Ok(buf.len())
@@ -175,3 +624,134 @@ impl Write for SenderWrite {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use serde_json::json;
+
+    use crate::{
+        messages::{Body, Echo, EchoOk, Message},
+        workloads::init::create_router,
+    };
+
+    use super::{FaultInjector, Faults, TestCluster};
+
+    /// `n0` sends a `ping` to `n1` for every `ping` echo, every echo is answered with the number
+    /// of pings the node received.
+    fn ping_cluster(faults: Faults) -> TestCluster {
+        TestCluster::new(2, || {
+            let mut router = create_router::<u64>();
+            router.on_raw("ping", |_, _, pings| {
+                *pings += 1;
+                Ok(())
+            });
+            router.on(|echo: Echo, ctx, pings| {
+                if echo.echo == "ping" {
+                    ctx.send("n1", Body::raw("ping", json!({}))?);
+                }
+                ctx.reply(Body::EchoOk(EchoOk {
+                    msg_id: None,
+                    in_reply_to: echo.msg_id,
+                    echo: pings.to_string(),
+                }));
+                Ok(())
+            });
+            router
+        })
+        .with_faults(faults)
+    }
+
+    fn pings(cluster: &mut TestCluster, node: &str, echo: &str) -> String {
+        let body = Body::Echo(Echo {
+            msg_id: 0,
+            echo: echo.to_string(),
+        });
+        match cluster.request(node, body).body {
+            Body::EchoOk(echo_ok) => echo_ok.echo,
+            body => panic!("Unexpected reply: {:?}", body),
+        }
+    }
+
+    fn ping(seq: u64) -> Message {
+        let body = Body::raw("ping", json!({ "seq": seq })).unwrap();
+        Message::new("n0", "n1", body)
+    }
+
+    #[test]
+    fn loss_of_one_should_drop_every_message_between_nodes() {
+        let mut cluster = ping_cluster(Faults::default().with_loss(1.0));
+        for _ in 0..3 {
+            pings(&mut cluster, "n0", "ping");
+        }
+        cluster.run_for(Duration::from_millis(100));
+        assert_eq!(pings(&mut cluster, "n1", "count"), "0");
+        assert_eq!(cluster.dropped_messages(), 3);
+    }
+
+    #[test]
+    fn duplication_of_one_should_deliver_every_message_twice() {
+        let mut cluster = ping_cluster(Faults::default().with_duplication(1.0));
+        pings(&mut cluster, "n0", "ping");
+        cluster.run_until(Duration::from_secs(1), |cluster| {
+            pings(cluster, "n1", "count") == "2"
+        });
+        cluster.run_for(Duration::from_millis(100));
+        assert_eq!(pings(&mut cluster, "n1", "count"), "2");
+    }
+
+    #[test]
+    fn same_seed_should_make_same_decisions_for_same_messages() {
+        let faults = Faults::default()
+            .with_loss(0.3)
+            .with_duplication(0.3)
+            .with_latency(Duration::ZERO, Duration::from_millis(100))
+            .with_seed(7);
+        // every ping is sent three times, retransmissions get their own decisions
+        let msgs: Vec<_> = (0..100)
+            .flat_map(|seq| [ping(seq), ping(seq), ping(seq)])
+            .collect();
+        let decide = |faults: Faults| {
+            let mut injector = FaultInjector::new(faults);
+            msgs.iter()
+                .map(|msg| injector.delays(msg))
+                .collect::<Vec<_>>()
+        };
+
+        let decisions = decide(faults.clone());
+        assert_eq!(decide(faults.clone()), decisions);
+        assert_ne!(decide(faults.clone().with_seed(8)), decisions);
+        // the order of other messages in between doesn't matter
+        let mut injector = FaultInjector::new(faults);
+        let reversed: Vec<_> = msgs.iter().rev().map(|msg| injector.delays(msg)).collect();
+        for seq in 0..100 {
+            let sends = &decisions[seq * 3..seq * 3 + 3];
+            let reversed_sends = &reversed[(99 - seq) * 3..(99 - seq) * 3 + 3];
+            assert_eq!(sends, reversed_sends);
+        }
+        assert!(decisions.iter().any(|delays| delays.is_empty()));
+        assert!(decisions.iter().any(|delays| delays.len() == 2));
+    }
+
+    #[test]
+    fn latency_should_stay_within_bounds() {
+        let (min, max) = (Duration::from_millis(10), Duration::from_millis(50));
+        let mut injector = FaultInjector::new(Faults::default().with_latency(min, max));
+        let delays: Vec<_> = (0..1000)
+            .flat_map(|seq| injector.delays(&ping(seq)))
+            .collect();
+        assert_eq!(delays.len(), 1000);
+        assert!(delays.iter().all(|delay| min <= *delay && *delay <= max));
+        assert!(
+            delays
+                .iter()
+                .any(|delay| *delay < Duration::from_millis(20))
+        );
+        assert!(
+            delays
+                .iter()
+                .any(|delay| *delay > Duration::from_millis(40))
+        );
+    }
+}
@@ -0,0 +1,224 @@
+use std::{
+    collections::HashMap,
+    fmt::{self, Debug},
+    sync::mpsc::Sender,
+    time::{Duration, Instant},
+};
+
+use crate::{Maelstrom, messages::Message, router::catch_panic};
+
+/// Called when a timer fires, repeating timers call the same callback every interval.
+pub type TimerCallback<U> = Box<dyn FnMut(&mut Sender<Message>, &mut Maelstrom<U>, &mut U) + Send>;
+
+/// Handle to cancel a scheduled timer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TimerId(u64);
+
+pub(crate) struct Timer<U> {
+    deadline: Instant,
+    interval: Option<Duration>,
+    /// Taken out while the callback runs, so the callback can cancel its own timer.
+    callback: Option<TimerCallback<U>>,
+}
+
+impl<U> Debug for Timer<U> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Timer")
+            .field("deadline", &self.deadline)
+            .field("interval", &self.interval)
+            .finish_non_exhaustive()
+    }
+}
+
+pub(crate) struct Timers<U> {
+    next_id: u64,
+    timers: HashMap<TimerId, Timer<U>>,
+}
+
+impl<U> Default for Timers<U> {
+    fn default() -> Self {
+        Self {
+            next_id: 0,
+            timers: HashMap::new(),
+        }
+    }
+}
+
+impl<U> Debug for Timers<U> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.timers.iter()).finish()
+    }
+}
+
+impl<U> Timers<U> {
+    pub(crate) fn schedule(
+        &mut self,
+        delay: Duration,
+        interval: Option<Duration>,
+        callback: TimerCallback<U>,
+    ) -> TimerId {
+        let id = TimerId(self.next_id);
+        self.next_id += 1;
+        self.timers.insert(
+            id,
+            Timer {
+                deadline: Instant::now() + delay,
+                interval,
+                callback: Some(callback),
+            },
+        );
+        id
+    }
+
+    pub(crate) fn next_deadline(&self) -> Option<Instant> {
+        self.timers.values().map(|timer| timer.deadline).min()
+    }
+}
+
+impl<U> Maelstrom<U> {
+    /// Calls `callback` once after `delay`.
+    pub fn set_timeout<F>(&mut self, delay: Duration, callback: F) -> TimerId
+    where
+        F: FnOnce(&mut Sender<Message>, &mut Maelstrom<U>, &mut U) + Send + 'static,
+    {
+        let mut callback = Some(callback);
+        self.timers.schedule(
+            delay,
+            None,
+            Box::new(move |tx, maelstrom, user_data| {
+                if let Some(callback) = callback.take() {
+                    callback(tx, maelstrom, user_data);
+                }
+            }),
+        )
+    }
+
+    /// Calls `callback` every `interval`, the first time after one `interval`.
+    pub fn set_interval<F>(&mut self, interval: Duration, callback: F) -> TimerId
+    where
+        F: FnMut(&mut Sender<Message>, &mut Maelstrom<U>, &mut U) + Send + 'static,
+    {
+        self.timers
+            .schedule(interval, Some(interval), Box::new(callback))
+    }
+
+    /// Returns `false` if the timer already fired or was cancelled before.
+    pub fn cancel_timer(&mut self, id: TimerId) -> bool {
+        self.timers.timers.remove(&id).is_some()
+    }
+
+    /// Runs the callbacks of all timers that are due, earliest deadline first. Timers scheduled
+    /// by the callbacks run on the next call at the earliest.
+    pub(crate) fn fire_timers(&mut self, tx: &mut Sender<Message>, user_data: &mut U) {
+        let now = Instant::now();
+        let mut due: Vec<(Instant, TimerId)> = self
+            .timers
+            .timers
+            .iter()
+            .filter(|(_, timer)| timer.deadline <= now)
+            .map(|(id, timer)| (timer.deadline, *id))
+            .collect();
+        due.sort_by_key(|(deadline, id)| (*deadline, id.0));
+
+        for (_, id) in due {
+            let Some(timer) = self.timers.timers.get_mut(&id) else {
+                // cancelled by an earlier callback
+                continue;
+            };
+            let Some(mut callback) = timer.callback.take() else {
+                continue;
+            };
+            match timer.interval {
+                // measured from now, a slow loop doesn't cause a burst of catch up calls
+                Some(interval) => timer.deadline = now + interval,
+                None => {
+                    self.timers.timers.remove(&id);
+                }
+            }
+
+            catch_panic("timer", || callback(tx, self, user_data));
+
+            if let Some(timer) = self.timers.timers.get_mut(&id) {
+                timer.callback = Some(callback);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{Arc, Mutex, mpsc},
+        thread,
+        time::Duration,
+    };
+
+    use crate::{Maelstrom, messages::Message};
+
+    fn fire_after(
+        maelstrom: &mut Maelstrom<Vec<&'static str>>,
+        data: &mut Vec<&'static str>,
+        delay: Duration,
+    ) {
+        thread::sleep(delay);
+        let (mut tx, _rx) = mpsc::channel::<Message>();
+        maelstrom.fire_timers(&mut tx, data);
+    }
+
+    #[test]
+    fn should_fire_one_shot_timers_once() {
+        let mut maelstrom = Maelstrom::default();
+        let mut fired = Vec::new();
+        maelstrom.set_timeout(Duration::from_millis(10), |_, _, fired: &mut Vec<_>| {
+            fired.push("once")
+        });
+        fire_after(&mut maelstrom, &mut fired, Duration::ZERO);
+        assert!(fired.is_empty());
+        assert!(maelstrom.next_deadline().is_some());
+
+        fire_after(&mut maelstrom, &mut fired, Duration::from_millis(20));
+        fire_after(&mut maelstrom, &mut fired, Duration::from_millis(20));
+        assert_eq!(fired, vec!["once"]);
+        assert!(maelstrom.next_deadline().is_none());
+    }
+
+    #[test]
+    fn should_repeat_interval_timers_until_cancelled() {
+        let mut maelstrom = Maelstrom::default();
+        let mut fired = Vec::new();
+        let id = maelstrom.set_interval(Duration::from_millis(10), |_, _, fired: &mut Vec<_>| {
+            fired.push("tick")
+        });
+        fire_after(&mut maelstrom, &mut fired, Duration::from_millis(15));
+        fire_after(&mut maelstrom, &mut fired, Duration::from_millis(15));
+        assert_eq!(fired, vec!["tick", "tick"]);
+
+        assert!(maelstrom.cancel_timer(id));
+        assert!(!maelstrom.cancel_timer(id));
+        fire_after(&mut maelstrom, &mut fired, Duration::from_millis(15));
+        assert_eq!(fired.len(), 2);
+    }
+
+    #[test]
+    fn should_allow_callbacks_to_cancel_their_own_timer() {
+        let mut maelstrom = Maelstrom::default();
+        let mut fired = Vec::new();
+        let id = Arc::new(Mutex::new(None));
+        let own_id = id.clone();
+        let timer = maelstrom.set_interval(
+            Duration::from_millis(5),
+            move |_, maelstrom: &mut Maelstrom<_>, fired: &mut Vec<_>| {
+                fired.push("last");
+                if let Some(id) = *own_id.lock().unwrap() {
+                    maelstrom.cancel_timer(id);
+                }
+            },
+        );
+        *id.lock().unwrap() = Some(timer);
+
+        fire_after(&mut maelstrom, &mut fired, Duration::from_millis(10));
+        fire_after(&mut maelstrom, &mut fired, Duration::from_millis(10));
+        assert_eq!(fired, vec!["last"]);
+        assert!(maelstrom.next_deadline().is_none());
+    }
+}
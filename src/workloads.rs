@@ -1,4 +1,7 @@
 pub mod broadcast;
 pub mod echo;
+pub mod g_counter;
 pub mod init;
+pub mod kafka;
+pub mod txn;
 pub mod unique_id;
@@ -4,17 +4,67 @@ use std::{
     time::{Duration, Instant},
 };
 
+use serde::{Deserialize, Serialize};
+
 use crate::{
-    Maelstrom,
-    messages::{Body, Broadcast, BroadcastOk, Message, Read, ReadOk, Topology, TopologyOk},
-    router::Router,
+    Maelstrom, debug, error, info,
+    messages::{
+        Body, Broadcast, BroadcastOk, Message, MessageType, Read, ReadOk, Topology, TopologyOk,
+    },
+    rand::SplitMix64,
+    router::{Context, HandlerResult, Router},
+    rpc::RpcOptions,
+    timer::TimerId,
+    trace,
 };
 
+use topology::TopologyStrategy;
+use values::{Digest, ValueStore};
+
+pub mod gossip;
+pub mod topology;
+pub mod values;
+
+/// First resend delay after a neighbor stopped acknowledging, doubled on every resend.
+const RETRY_BASE: Duration = Duration::from_millis(200);
+/// Upper bound for the resend delay, so healed partitions are noticed quickly enough.
+const RETRY_CAP: Duration = Duration::from_secs(5);
+
+/// How often every neighbor is asked whether it has values we are missing.
+const ANTI_ENTROPY_INTERVAL: Duration = Duration::from_secs(1);
+
 #[derive(Debug, Default)]
 pub struct SimpleBroadcast {
-    messages: Vec<serde_json::Value>,
+    messages: ValueStore,
     neighbors: Vec<Neighboar>,
-    unack_messages: HashMap<u64, (Instant, Message)>,
+    /// Forwarded broadcasts by neighbor and msg_id, until the neighbor acknowledges them.
+    unack_messages: HashMap<(String, u64), (Instant, Message)>,
+    topology: TopologyStrategy,
+    /// Random jitter added to resend delays, so nodes don't retry in lockstep.
+    jitter: SplitMix64,
+}
+
+/// Anti-entropy request, carries the bucket digests of the sender's values. A neighbor answers
+/// with its values of the buckets that differ, so the sender can pull the ones it missed, e.g.
+/// while it was partitioned, without receiving every value each round.
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
+pub struct SyncDigests {
+    pub msg_id: u64,
+    pub buckets: Vec<Digest>,
+}
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
+pub struct SyncDigestsOk {
+    pub in_reply_to: u64,
+    /// Empty if both nodes already have the same values.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub messages: Vec<serde_json::Value>,
+}
+
+impl MessageType for SyncDigests {
+    const TYPE: &'static str = "sync";
+}
+impl MessageType for SyncDigestsOk {
+    const TYPE: &'static str = "sync_ok";
 }
 
 #[derive(Debug, Default, Clone)]
@@ -22,59 +72,219 @@ struct Neighboar {
     name: String,
     newest_unack: Option<Instant>,
     newest_ack: Option<Instant>,
+    /// Resends since the neighbor last acknowledged a broadcast.
+    retries: u32,
+    next_retry: Option<Instant>,
+    /// Timeout that resends at `next_retry`.
+    retry_timer: Option<(Instant, TimerId)>,
+}
+
+impl Neighboar {
+    fn new(name: String) -> Self {
+        Self {
+            name,
+            ..Default::default()
+        }
+    }
+
+    /// The neighbor has not acknowledged anything since we last sent it a broadcast.
+    fn is_behind(&self) -> bool {
+        match (self.newest_unack, self.newest_ack) {
+            (Some(unack), Some(ack)) => unack > ack,
+            (Some(_), None) => true,
+            _ => false,
+        }
+    }
+
+    /// Exponential backoff with up to 50% jitter: `base * 2^retries`, capped.
+    fn schedule_retry(&mut self, now: Instant, jitter: &mut SplitMix64) {
+        let delay = RETRY_BASE
+            .saturating_mul(1 << self.retries.min(16))
+            .min(RETRY_CAP);
+        let jitter = delay.mul_f64((jitter.next() % 1000) as f64 / 2000.0);
+        self.next_retry = Some(now + delay + jitter);
+    }
 }
 
 impl SimpleBroadcast {
-    fn store(&mut self, node: &str, msg: Message, msg_id: u64) {
-        let timestamp = Instant::now();
+    pub fn with_topology(mut self, topology: TopologyStrategy) -> Self {
+        self.topology = topology;
+        self
+    }
+
+    fn store(&mut self, node: &str, msg: Message, msg_id: u64, timestamp: Instant) {
         let msg = (timestamp, msg);
-        self.unack_messages.insert(msg_id, msg);
+        self.unack_messages.insert((node.to_string(), msg_id), msg);
         for neighboar in self.neighbors.iter_mut() {
             if neighboar.name == node {
+                // a neighbor that is already backing off keeps its schedule
+                if neighboar.next_retry.is_none() {
+                    neighboar.schedule_retry(timestamp, &mut self.jitter);
+                }
                 neighboar.newest_unack = Some(timestamp);
             }
         }
     }
 
-    fn clear(&mut self, node: &str, msg_id: u64) {
-        if let Some((timestamp, msg)) = self.unack_messages.remove(&msg_id) {
+    /// Unacknowledged broadcasts to resend to `node` if its backoff elapsed at `now`.
+    fn resend(&mut self, node: &str, now: Instant) -> Vec<Message> {
+        let Some(neighboar) = self.neighbors.iter_mut().find(|n| n.name == node) else {
+            return Vec::new();
+        };
+        if neighboar
+            .next_retry
+            .is_none_or(|next_retry| next_retry > now)
+        {
+            return Vec::new();
+        }
+        let unacked: Vec<Message> = self
+            .unack_messages
+            .iter()
+            .filter(|((dest, _), _)| dest == node)
+            .map(|(_, (_, msg))| msg.clone())
+            .collect();
+        if unacked.is_empty() {
+            neighboar.next_retry = None;
+            return unacked;
+        }
+        if neighboar.is_behind() {
+            neighboar.retries += 1;
+        }
+        neighboar.newest_unack = Some(now);
+        neighboar.schedule_retry(now, &mut self.jitter);
+        unacked
+    }
+
+    fn clear(&mut self, node: &str, msg_id: u64, now: Instant) {
+        if self
+            .unack_messages
+            .remove(&(node.to_string(), msg_id))
+            .is_some()
+        {
+            let pending = self.unack_messages.keys().any(|(dest, _)| dest == node);
             for neighboar in self.neighbors.iter_mut() {
                 if neighboar.name == node {
-                    neighboar.newest_ack = Some(Instant::now());
+                    // the neighbor is reachable again, start over with the shortest delay
+                    neighboar.newest_ack = Some(now);
+                    neighboar.retries = 0;
+                    neighboar.next_retry = None;
+                    if pending {
+                        neighboar.schedule_retry(now, &mut self.jitter);
+                    }
                 }
             }
         }
     }
 }
 
-fn tick(tx: &mut Sender<Message>, maelstrom: &mut Maelstrom, data: &mut SimpleBroadcast) {
-    for (_, (timestamp, msg)) in data.unack_messages.clone() {
-        if Duration::from_millis(200) < timestamp.elapsed() {
-            eprintln!("Resending: {:?}", msg);
-            tx.send(msg).unwrap();
+/// Sets a timeout for the next resend to `node`, or cancels it if nothing needs resending.
+fn arm_retry(maelstrom: &mut Maelstrom<SimpleBroadcast>, data: &mut SimpleBroadcast, node: &str) {
+    let Some(neighboar) = data.neighbors.iter_mut().find(|n| n.name == node) else {
+        return;
+    };
+    let armed = neighboar.retry_timer.map(|(deadline, _)| deadline);
+    if armed == neighboar.next_retry {
+        return;
+    }
+    if let Some((_, timer)) = neighboar.retry_timer.take() {
+        maelstrom.cancel_timer(timer);
+    }
+    if let Some(next_retry) = neighboar.next_retry {
+        let node = node.to_string();
+        let delay = next_retry.saturating_duration_since(Instant::now());
+        let timer = maelstrom.set_timeout(delay, move |tx, maelstrom, data| {
+            retransmit(tx, maelstrom, data, &node)
+        });
+        neighboar.retry_timer = Some((next_retry, timer));
+    }
+}
+
+/// Resends unacknowledged broadcasts to `node` once its backoff elapsed.
+fn retransmit(
+    tx: &mut Sender<Message>,
+    maelstrom: &mut Maelstrom<SimpleBroadcast>,
+    data: &mut SimpleBroadcast,
+    node: &str,
+) {
+    if let Some(neighboar) = data.neighbors.iter_mut().find(|n| n.name == node) {
+        neighboar.retry_timer = None;
+    }
+    for msg in data.resend(node, Instant::now()) {
+        debug!("Resending: {:?}", msg);
+        // only fails once the server stopped
+        let _ = tx.send(msg);
+    }
+    arm_retry(maelstrom, data, node);
+}
+
+/// Sends our bucket digests to every neighbor and stores whatever values they answer with.
+fn anti_entropy(
+    tx: &mut Sender<Message>,
+    maelstrom: &mut Maelstrom<SimpleBroadcast>,
+    data: &mut SimpleBroadcast,
+) {
+    // msg_id is assigned by the rpc
+    let body = match Body::encode(&SyncDigests {
+        msg_id: 0,
+        buckets: data.messages.buckets(),
+    }) {
+        Ok(body) => body,
+        Err(err) => {
+            error!("Could not encode sync: {}", err);
+            return;
         }
+    };
+    // an unanswered round is simply repeated next interval
+    let options = RpcOptions::default().with_timeout(ANTI_ENTROPY_INTERVAL);
+    for neighboar in data.neighbors.iter() {
+        maelstrom.rpc(
+            tx,
+            &neighboar.name,
+            body.clone(),
+            options,
+            |result, _, _, data| {
+                let Ok(body) = result else { return };
+                if let Ok(sync_ok) = body.decode::<SyncDigestsOk>() {
+                    for message in sync_ok.messages {
+                        data.messages.insert(message);
+                    }
+                }
+            },
+        );
     }
 }
 
+fn sync(
+    sync: SyncDigests,
+    ctx: &mut Context<SimpleBroadcast>,
+    data: &mut SimpleBroadcast,
+) -> HandlerResult {
+    let messages = data.messages.missing_from(&sync.buckets);
+    let body = Body::encode(&SyncDigestsOk {
+        in_reply_to: sync.msg_id,
+        messages,
+    })?;
+    ctx.reply(body);
+    Ok(())
+}
+
 fn broadcast(
     broadcast: Broadcast,
-    tx: &mut Sender<Message>,
-    src: &str,
-    maelstrom: &mut Maelstrom,
+    ctx: &mut Context<SimpleBroadcast>,
     data: &mut SimpleBroadcast,
-) {
+) -> HandlerResult {
     // only broadcast message to neighbors if we haven't stored it yet
-    if !data.messages.contains(&broadcast.message) {
-        let msg_id = maelstrom.generate_id();
+    if data.messages.insert(broadcast.message.clone()) {
+        let msg_id = ctx.generate_id();
         let mut broadcast_neighbors = broadcast.clone();
         broadcast_neighbors.msg_id = msg_id;
         let broadcast_neighbors = Body::Broadcast(broadcast_neighbors);
-        data.messages.push(broadcast.message);
 
         for neighboar in data.neighbors.clone() {
-            let msg = maelstrom.create_message(&neighboar.name, broadcast_neighbors.clone());
-            data.store(src, msg.clone(), msg_id);
-            tx.send(msg).unwrap();
+            let msg = ctx.create_message(&neighboar.name, broadcast_neighbors.clone());
+            data.store(&neighboar.name, msg.clone(), msg_id, Instant::now());
+            arm_retry(ctx, data, &neighboar.name);
+            ctx.send_message(msg);
         }
     }
 
@@ -83,80 +293,89 @@ fn broadcast(
         msg_id: None,
         in_reply_to: broadcast.msg_id,
     });
-    let msg = maelstrom.create_message(src, body);
-    tx.send(msg).unwrap();
+    ctx.reply(body);
+    Ok(())
 }
 
 fn broadcast_ok(
     broadcast_ok: BroadcastOk,
-    tx: &mut Sender<Message>,
-    src: &str,
-    maelstrom: &mut Maelstrom,
+    ctx: &mut Context<SimpleBroadcast>,
     data: &mut SimpleBroadcast,
-) {
-    eprintln!("Received from {}: {:?}", src, broadcast_ok);
-    data.clear(src, broadcast_ok.in_reply_to);
+) -> HandlerResult {
+    trace!("Received from {}: {:?}", ctx.src(), broadcast_ok);
+    let src = ctx.src().to_string();
+    data.clear(&src, broadcast_ok.in_reply_to, Instant::now());
+    arm_retry(ctx, data, &src);
+    Ok(())
 }
 
 fn read(
     read: Read,
-    tx: &mut Sender<Message>,
-    src: &str,
-    maelstrom: &mut Maelstrom,
+    ctx: &mut Context<SimpleBroadcast>,
     data: &mut SimpleBroadcast,
-) {
+) -> HandlerResult {
     let body = Body::ReadOk(ReadOk {
         msg_id: None,
         in_reply_to: read.msg_id,
-        messages: data.messages.clone(),
+        messages: data.messages.as_slice().to_vec(),
     });
-    let msg = maelstrom.create_message(src, body);
-    tx.send(msg).unwrap();
+    ctx.reply(body);
+    Ok(())
 }
 
 fn topology(
     topology: Topology,
-    tx: &mut Sender<Message>,
-    src: &str,
-    maelstrom: &mut Maelstrom,
+    ctx: &mut Context<SimpleBroadcast>,
     data: &mut SimpleBroadcast,
-) {
-    if let Some(neighbors) = topology.topology.get(&maelstrom.node_id) {
-        for neighboar in neighbors {
-            data.neighbors.push(Neighboar {
-                name: neighboar.to_string(),
-                newest_unack: None,
-                newest_ack: None,
-            });
-        }
+) -> HandlerResult {
+    let neighbors = data
+        .topology
+        .neighbors(ctx.node_id(), ctx.node_ids(), &topology.topology);
+    for neighboar in neighbors {
+        data.neighbors.push(Neighboar::new(neighboar));
+    }
+    data.jitter = SplitMix64::from_node(ctx.node_id());
 
-        eprintln!("Topology: {:#?}", data.neighbors);
+    info!("Topology: {:?}", data.neighbors);
 
-        let body = Body::TopologyOk(TopologyOk {
-            msg_id: None,
-            in_reply_to: topology.msg_id,
-        });
-        let msg = maelstrom.create_message(src, body);
-        tx.send(msg).unwrap();
-    }
+    let body = Body::TopologyOk(TopologyOk {
+        msg_id: None,
+        in_reply_to: topology.msg_id,
+    });
+    ctx.reply(body);
+    Ok(())
 }
 
 pub fn insert_broadcast_simple_handlers(router: &mut Router<SimpleBroadcast>) {
-    router.set_tick(tick);
+    router.every(ANTI_ENTROPY_INTERVAL, anti_entropy);
 
     router.on(broadcast);
     router.on(broadcast_ok);
     router.on(read);
+    router.on(sync);
     router.on(topology);
 }
 
 #[cfg(test)]
 mod tests {
+    use std::{
+        cell::Cell,
+        collections::HashMap,
+        time::{Duration, Instant},
+    };
+
     use serde_json::json;
 
-    use crate::{messages::Body, testing, workloads::init::create_router};
+    use crate::{
+        messages::{Body, Broadcast, Message, Read, Topology},
+        testing,
+        workloads::init::create_router,
+    };
 
-    use super::{SimpleBroadcast, insert_broadcast_simple_handlers};
+    use super::{
+        Neighboar, RETRY_BASE, RETRY_CAP, SimpleBroadcast, SplitMix64, SyncDigests, SyncDigestsOk,
+        insert_broadcast_simple_handlers,
+    };
     #[test]
     fn should_respond_with_broadcast_ok() {
         let mut router = create_router::<SimpleBroadcast>();
@@ -250,4 +469,245 @@ mod tests {
                 }
             });
     }
+
+    #[test]
+    fn should_pull_missing_values_from_neighbors() {
+        let mut router = create_router::<SimpleBroadcast>();
+        insert_broadcast_simple_handlers(&mut router);
+        let server = testing::TestServer::from_router(router)
+            .send_str(r#"{"src":"c0","dest":"n0","body":{"type":"init","node_id":"n0","node_ids":["n0","n1"],"msg_id":1}}"#)
+            .send_str(r#"{"src":"c1","dest":"n0","body":{"type":"topology","topology":{"n0":["n1"]},"msg_id":2}}"#)
+            .assert_msg_received_timeout(
+                |msg| msg.dest == "n1" && msg.body.decode::<SyncDigests>().is_ok_and(|sync| sync.buckets.iter().all(|bucket| bucket.count == 0)),
+                Duration::from_secs(2),
+            );
+        let msg_id = server
+            .get_messages()
+            .iter()
+            .find(|msg| msg.body.type_name() == "sync")
+            .and_then(|msg| msg.body.msg_id())
+            .unwrap();
+
+        server
+            .send_str(&format!(
+                r#"{{"src":"n1","dest":"n0","body":{{"type":"sync_ok","messages":[4,5],"in_reply_to":{}}}}}"#,
+                msg_id
+            ))
+            .send_str(r#"{"src":"c1","dest":"n0","body":{"type":"read","msg_id":3}}"#)
+            .assert_msg_received_default_timeout(|msg| {
+                matches!(&msg.body, Body::ReadOk(read_ok) if read_ok.messages == vec![json!(4), json!(5)])
+            });
+    }
+
+    #[test]
+    fn should_answer_sync_with_values_only_when_buckets_differ() {
+        let mut router = create_router::<SimpleBroadcast>();
+        insert_broadcast_simple_handlers(&mut router);
+        testing::TestServer::from_router(router)
+            .send_str(
+                r#"{"src":"c1","dest":"n0","body":{"type":"broadcast","message":4,"msg_id":1}}"#,
+            )
+            .send_str(r#"{"src":"n1","dest":"n0","body":{"type":"sync","buckets":[],"msg_id":2}}"#)
+            .assert_msg_received_default_timeout(|msg| {
+                msg.body.decode::<SyncDigestsOk>().is_ok_and(|sync_ok| {
+                    sync_ok.in_reply_to == 2 && sync_ok.messages == vec![json!(4)]
+                })
+            });
+
+        let buckets = [json!(4)]
+            .into_iter()
+            .collect::<super::ValueStore>()
+            .buckets();
+        let mut router = create_router::<SimpleBroadcast>();
+        insert_broadcast_simple_handlers(&mut router);
+        testing::TestServer::from_router(router)
+            .send_str(
+                r#"{"src":"c1","dest":"n0","body":{"type":"broadcast","message":4,"msg_id":1}}"#,
+            )
+            .send_str(&format!(
+                r#"{{"src":"n1","dest":"n0","body":{{"type":"sync","buckets":{},"msg_id":2}}}}"#,
+                serde_json::to_string(&buckets).unwrap()
+            ))
+            .assert_msg_received_default_timeout(|msg| {
+                msg.body
+                    .decode::<SyncDigestsOk>()
+                    .is_ok_and(|sync_ok| sync_ok.in_reply_to == 2 && sync_ok.messages.is_empty())
+            });
+    }
+
+    /// Nodes `n0` to `n4` in a line, so values have to be forwarded over several hops.
+    fn line_cluster(faults: testing::Faults) -> testing::TestCluster {
+        let mut cluster = testing::TestCluster::new(5, || {
+            let mut router = create_router::<SimpleBroadcast>();
+            insert_broadcast_simple_handlers(&mut router);
+            router
+        })
+        .with_faults(faults);
+        let node_ids = cluster.node_ids().to_vec();
+        let topology = node_ids
+            .iter()
+            .enumerate()
+            .map(|(i, node)| {
+                let neighbors = [i.checked_sub(1), Some(i + 1)]
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|j| node_ids.get(j).cloned())
+                    .collect();
+                (node.clone(), neighbors)
+            })
+            .collect::<HashMap<_, _>>();
+        for node in &node_ids {
+            let body = Body::Topology(Topology {
+                msg_id: 0,
+                topology: topology.clone(),
+            });
+            cluster.request(node, body);
+        }
+        cluster
+    }
+
+    /// Broadcasts the index of every node to that node.
+    fn broadcast_to_all(cluster: &mut testing::TestCluster) {
+        for (value, node) in cluster.node_ids().to_vec().iter().enumerate() {
+            let body = Body::Broadcast(Broadcast {
+                message: json!(value),
+                msg_id: 0,
+            });
+            cluster.request(node, body);
+        }
+    }
+
+    fn read_values(cluster: &mut testing::TestCluster, node: &str) -> Vec<u64> {
+        let reply = cluster.request(node, Body::Read(Read { msg_id: 0 }));
+        let Body::ReadOk(read_ok) = reply.body else {
+            panic!("Unexpected reply to read: {:?}", reply);
+        };
+        let mut values: Vec<u64> = read_ok.messages.iter().filter_map(|v| v.as_u64()).collect();
+        values.sort();
+        values
+    }
+
+    fn all_nodes_read_every_value(cluster: &mut testing::TestCluster) -> bool {
+        let node_ids = cluster.node_ids().to_vec();
+        let expected: Vec<u64> = (0..node_ids.len() as u64).collect();
+        node_ids
+            .iter()
+            .all(|node| read_values(cluster, node) == expected)
+    }
+
+    #[test]
+    fn all_nodes_should_read_every_broadcast_value() {
+        let mut cluster = line_cluster(testing::Faults::default());
+        broadcast_to_all(&mut cluster);
+        cluster.run_until(Duration::from_secs(5), all_nodes_read_every_value);
+    }
+
+    #[test]
+    fn all_nodes_should_read_every_value_despite_lossy_network() {
+        let faults = testing::Faults::default()
+            .with_loss(0.3)
+            .with_duplication(0.2)
+            .with_latency(Duration::ZERO, Duration::from_millis(30))
+            .with_seed(25);
+        let mut cluster = line_cluster(faults);
+        broadcast_to_all(&mut cluster);
+        cluster.run_until(Duration::from_secs(10), all_nodes_read_every_value);
+    }
+
+    #[test]
+    fn all_nodes_should_read_every_value_once_partition_heals() {
+        let mut cluster = line_cluster(testing::Faults::default());
+        cluster.partition(&["n0", "n1"], &["n2", "n3", "n4"]);
+        broadcast_to_all(&mut cluster);
+        cluster.run_for(Duration::from_millis(500));
+        assert_eq!(read_values(&mut cluster, "n0"), vec![0, 1]);
+        assert_eq!(read_values(&mut cluster, "n4"), vec![2, 3, 4]);
+
+        cluster.heal();
+        cluster.run_until(Duration::from_secs(10), all_nodes_read_every_value);
+    }
+
+    #[test]
+    fn retry_delay_grows_exponentially_up_to_cap() {
+        let now = Instant::now();
+        let mut jitter = SplitMix64::from_node("n0");
+        let mut neighboar = Neighboar::new("n1".to_string());
+        for retries in [0, 1, 2, 30] {
+            neighboar.retries = retries;
+            neighboar.schedule_retry(now, &mut jitter);
+            let delay = neighboar.next_retry.unwrap() - now;
+            let expected = RETRY_BASE
+                .saturating_mul(1 << retries.min(16))
+                .min(RETRY_CAP);
+            assert!(
+                expected <= delay && delay <= expected.mul_f64(1.5),
+                "{} retries: {:?}",
+                retries,
+                delay
+            );
+        }
+    }
+
+    #[test]
+    fn should_resend_to_neighbor_that_does_not_ack() {
+        let mut router = create_router::<SimpleBroadcast>();
+        insert_broadcast_simple_handlers(&mut router);
+        let server = testing::TestServer::from_router(router)
+            .send_str(r#"{"src":"c0","dest":"n0","body":{"type":"init","node_id":"n0","node_ids":["n0","n1"],"msg_id":1}}"#)
+            .send_str(r#"{"src":"c1","dest":"n0","body":{"type":"topology","topology":{"n0":["n1"]},"msg_id":2}}"#)
+            .send_str(r#"{"src":"c1","dest":"n0","body":{"type":"broadcast","message":1,"msg_id":3}}"#);
+        // the first resend is due after 200ms to 300ms
+        let sent = Cell::new(0);
+        server.assert_msg_received_timeout(
+            |msg| {
+                if msg.dest == "n1" && matches!(msg.body, Body::Broadcast(_)) {
+                    sent.set(sent.get() + 1);
+                }
+                sent.get() == 2
+            },
+            Duration::from_millis(500),
+        );
+    }
+
+    #[test]
+    fn should_back_off_when_neighbor_does_not_ack() {
+        let mut data = SimpleBroadcast {
+            neighbors: vec![Neighboar::new("n1".to_string())],
+            jitter: SplitMix64::from_node("n0"),
+            ..Default::default()
+        };
+        let body = Body::Broadcast(Broadcast {
+            message: json!(1),
+            msg_id: 1,
+        });
+        let start = Instant::now();
+        data.store("n1", Message::new("n0", "n1", body), 1, start);
+
+        // resent after roughly 200ms, 400ms, 800ms, ... instead of every tick
+        let step = Duration::from_millis(10);
+        let mut last_sent = start;
+        let mut resends = 0;
+        let mut now = start;
+        while now < start + Duration::from_secs(20) {
+            if !data.resend("n1", now).is_empty() {
+                let expected = RETRY_BASE
+                    .saturating_mul(1 << resends.min(16))
+                    .min(RETRY_CAP);
+                let delay = now - last_sent;
+                assert!(
+                    expected <= delay && delay <= expected.mul_f64(1.5) + step,
+                    "resend {}: {:?}",
+                    resends,
+                    delay
+                );
+                last_sent = now;
+                resends += 1;
+            }
+            now += step;
+        }
+        assert!(resends >= 6, "resent {} times", resends);
+
+        data.clear("n1", 1, now);
+        assert!(data.resend("n1", now + RETRY_CAP * 2).is_empty());
+    }
 }
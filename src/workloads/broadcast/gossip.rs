@@ -0,0 +1,325 @@
+use std::{collections::HashMap, sync::mpsc::Sender, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    Maelstrom, error,
+    messages::{
+        Body, Broadcast, BroadcastOk, Message, MessageType, Read, ReadOk, Topology, TopologyOk,
+    },
+    router::{Context, HandlerResult, Router},
+    rpc::RpcOptions,
+    timer::TimerId,
+};
+
+use super::{topology::TopologyStrategy, values::ValueStore};
+
+const DEFAULT_GOSSIP_INTERVAL: Duration = Duration::from_millis(150);
+
+/// Broadcast that batches values: instead of forwarding every value on its own, each node
+/// periodically sends every neighbor all values the neighbor has not acknowledged yet.
+#[derive(Debug)]
+pub struct GossipBroadcast {
+    messages: ValueStore,
+    /// Values not yet acknowledged by each neighbor.
+    unacked: HashMap<String, Vec<serde_json::Value>>,
+    gossip_interval: Duration,
+    /// Started with the first `topology` message, there is no one to gossip with before.
+    gossip_timer: Option<TimerId>,
+    topology: TopologyStrategy,
+}
+
+impl Default for GossipBroadcast {
+    fn default() -> Self {
+        Self::new(DEFAULT_GOSSIP_INTERVAL)
+    }
+}
+
+impl GossipBroadcast {
+    pub fn new(gossip_interval: Duration) -> Self {
+        Self {
+            messages: ValueStore::default(),
+            unacked: HashMap::new(),
+            gossip_interval,
+            gossip_timer: None,
+            topology: TopologyStrategy::default(),
+        }
+    }
+
+    pub fn with_topology(mut self, topology: TopologyStrategy) -> Self {
+        self.topology = topology;
+        self
+    }
+
+    /// Stores the value and queues it for every neighbor except `src`, which already has it.
+    fn store(&mut self, src: &str, message: serde_json::Value) {
+        if !self.messages.insert(message.clone()) {
+            return;
+        }
+        for (neighbor, unacked) in self.unacked.iter_mut() {
+            if neighbor != src {
+                unacked.push(message.clone());
+            }
+        }
+    }
+
+    fn ack(&mut self, neighbor: &str, messages: &[serde_json::Value]) {
+        if let Some(unacked) = self.unacked.get_mut(neighbor) {
+            let acked: ValueStore = messages.iter().cloned().collect();
+            unacked.retain(|message| !acked.contains(message));
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
+pub struct Gossip {
+    pub msg_id: u64,
+    pub messages: Vec<serde_json::Value>,
+}
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
+pub struct GossipOk {
+    pub in_reply_to: u64,
+}
+
+impl MessageType for Gossip {
+    const TYPE: &'static str = "gossip";
+}
+impl MessageType for GossipOk {
+    const TYPE: &'static str = "gossip_ok";
+}
+
+fn gossip_round(
+    tx: &mut Sender<Message>,
+    maelstrom: &mut Maelstrom<GossipBroadcast>,
+    data: &mut GossipBroadcast,
+) {
+    // values that were not acknowledged are sent again next round anyway, the timeout only
+    // bounds how long a late `gossip_ok` is still accepted
+    let options = RpcOptions::default().with_timeout(data.gossip_interval * 4);
+    for (neighbor, unacked) in data.unacked.iter() {
+        if unacked.is_empty() {
+            continue;
+        }
+        let body = match Body::encode(&Gossip {
+            msg_id: 0,
+            messages: unacked.clone(),
+        }) {
+            Ok(body) => body,
+            Err(err) => {
+                error!("Could not encode gossip: {}", err);
+                continue;
+            }
+        };
+        let acked_by = neighbor.clone();
+        let sent = unacked.clone();
+        maelstrom.rpc(tx, neighbor, body, options, move |result, _, _, data| {
+            if result.is_ok() {
+                data.ack(&acked_by, &sent);
+            }
+        });
+    }
+}
+
+fn gossip(
+    gossip: Gossip,
+    ctx: &mut Context<GossipBroadcast>,
+    data: &mut GossipBroadcast,
+) -> HandlerResult {
+    // the sender obviously has these values, no need to send them back
+    data.ack(ctx.src(), &gossip.messages);
+    for message in gossip.messages {
+        data.store(ctx.src(), message);
+    }
+
+    let body = Body::encode(&GossipOk {
+        in_reply_to: gossip.msg_id,
+    })?;
+    ctx.reply(body);
+    Ok(())
+}
+
+fn broadcast(
+    broadcast: Broadcast,
+    ctx: &mut Context<GossipBroadcast>,
+    data: &mut GossipBroadcast,
+) -> HandlerResult {
+    data.store(ctx.src(), broadcast.message);
+
+    let body = Body::BroadcastOk(BroadcastOk {
+        msg_id: None,
+        in_reply_to: broadcast.msg_id,
+    });
+    ctx.reply(body);
+    Ok(())
+}
+
+fn read(
+    read: Read,
+    ctx: &mut Context<GossipBroadcast>,
+    data: &mut GossipBroadcast,
+) -> HandlerResult {
+    let body = Body::ReadOk(ReadOk {
+        msg_id: None,
+        in_reply_to: read.msg_id,
+        messages: data.messages.as_slice().to_vec(),
+    });
+    ctx.reply(body);
+    Ok(())
+}
+
+fn topology(
+    topology: Topology,
+    ctx: &mut Context<GossipBroadcast>,
+    data: &mut GossipBroadcast,
+) -> HandlerResult {
+    let neighbors = data
+        .topology
+        .neighbors(ctx.node_id(), ctx.node_ids(), &topology.topology);
+    for neighbor in neighbors {
+        // neighbors learning about us later still need every value we already have
+        data.unacked
+            .entry(neighbor)
+            .or_insert_with(|| data.messages.as_slice().to_vec());
+    }
+    if data.gossip_timer.is_none() {
+        data.gossip_timer = Some(ctx.set_interval(data.gossip_interval, gossip_round));
+    }
+
+    let body = Body::TopologyOk(TopologyOk {
+        msg_id: None,
+        in_reply_to: topology.msg_id,
+    });
+    ctx.reply(body);
+    Ok(())
+}
+
+pub fn insert_broadcast_gossip_handlers(router: &mut Router<GossipBroadcast>) {
+    router.on(broadcast);
+    router.on(gossip);
+    router.on(read);
+    router.on(topology);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use serde_json::json;
+
+    use crate::{
+        messages::{Body, Message},
+        testing,
+        workloads::init::create_router,
+    };
+
+    use super::{Gossip, GossipBroadcast, TopologyStrategy, insert_broadcast_gossip_handlers};
+
+    const INIT: &str = r#"{"src":"c0","dest":"n0","body":{"type":"init","node_id":"n0","node_ids":["n0","n1","n2"],"msg_id":1}}"#;
+    const TOPOLOGY: &str = r#"{"src":"c0","dest":"n0","body":{"type":"topology","topology":{"n0":["n1","n2"]},"msg_id":2}}"#;
+
+    fn is_gossip(msg: &Message, dest: &str, messages: &[serde_json::Value]) -> bool {
+        msg.dest == dest
+            && msg
+                .body
+                .decode::<Gossip>()
+                .is_ok_and(|gossip| msg.body.type_name() == "gossip" && gossip.messages == messages)
+    }
+
+    #[test]
+    fn should_batch_broadcast_values_per_neighbor() {
+        let mut router = create_router::<GossipBroadcast>();
+        insert_broadcast_gossip_handlers(&mut router);
+        testing::TestServer::from_router(router)
+            .send_str(INIT)
+            .send_str(TOPOLOGY)
+            .send_str(
+                r#"{"src":"c1","dest":"n0","body":{"type":"broadcast","message":1,"msg_id":3}}"#,
+            )
+            .send_str(
+                r#"{"src":"c1","dest":"n0","body":{"type":"broadcast","message":2,"msg_id":4}}"#,
+            )
+            .assert_msg_received_default_timeout(|msg| matches!(msg.body, Body::BroadcastOk(_)))
+            .assert_msg_received_timeout(
+                |msg| is_gossip(msg, "n1", &[json!(1), json!(2)]),
+                Duration::from_secs(1),
+            );
+    }
+
+    #[test]
+    fn should_not_gossip_values_back_to_their_sender() {
+        let mut router = create_router::<GossipBroadcast>();
+        insert_broadcast_gossip_handlers(&mut router);
+        let server = testing::TestServer::from_router(router)
+            .send_str(INIT)
+            .send_str(TOPOLOGY)
+            .send_str(r#"{"src":"n1","dest":"n0","body":{"type":"gossip","messages":[7],"msg_id":1}}"#)
+            .assert_msg_received_default_timeout(|msg| msg.dest == "n1" && msg.body.in_reply_to() == Some(1))
+            .assert_msg_received_timeout(
+                |msg| is_gossip(msg, "n2", &[json!(7)]),
+                Duration::from_secs(1),
+            )
+            .send_str(r#"{"src":"c1","dest":"n0","body":{"type":"read","msg_id":5}}"#)
+            .assert_msg_received_default_timeout(|msg| {
+                matches!(&msg.body, Body::ReadOk(read_ok) if read_ok.messages == vec![json!(7)])
+            });
+
+        assert!(
+            !server
+                .get_messages()
+                .iter()
+                .any(|msg| is_gossip(msg, "n1", &[json!(7)]))
+        );
+    }
+
+    #[test]
+    fn should_pick_neighbors_with_topology_strategy() {
+        let mut router = create_router::<GossipBroadcast>();
+        insert_broadcast_gossip_handlers(&mut router);
+        testing::TestServer::from_router_with_data(
+            router,
+            GossipBroadcast::default().with_topology(TopologyStrategy::Star),
+        )
+        .send_str(r#"{"src":"c0","dest":"n1","body":{"type":"init","node_id":"n1","node_ids":["n0","n1","n2"],"msg_id":1}}"#)
+        .send_str(r#"{"src":"c0","dest":"n1","body":{"type":"topology","topology":{"n1":["n2"]},"msg_id":2}}"#)
+        .send_str(r#"{"src":"c1","dest":"n1","body":{"type":"broadcast","message":1,"msg_id":3}}"#)
+        .assert_msg_received_timeout(|msg| is_gossip(msg, "n0", &[json!(1)]), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn should_stop_gossiping_acknowledged_values() {
+        let mut router = create_router::<GossipBroadcast>();
+        insert_broadcast_gossip_handlers(&mut router);
+        let gossip_interval = Duration::from_millis(20);
+        let server = testing::TestServer::from_router_with_data(router, GossipBroadcast::new(gossip_interval))
+            .send_str(INIT)
+            .send_str(r#"{"src":"c0","dest":"n0","body":{"type":"topology","topology":{"n0":["n1"]},"msg_id":2}}"#)
+            .send_str(r#"{"src":"c1","dest":"n0","body":{"type":"broadcast","message":1,"msg_id":3}}"#)
+            .assert_msg_received_timeout(|msg| is_gossip(msg, "n1", &[json!(1)]), Duration::from_secs(1));
+        let msg_id = server
+            .get_messages()
+            .iter()
+            .find(|msg| is_gossip(msg, "n1", &[json!(1)]))
+            .and_then(|msg| msg.body.msg_id())
+            .unwrap();
+
+        // messages are handled in order, everything sent after the `read_ok` was sent after the ack
+        let server = server
+            .send_str(&format!(
+                r#"{{"src":"n1","dest":"n0","body":{{"type":"gossip_ok","in_reply_to":{}}}}}"#,
+                msg_id
+            ))
+            .send_str(r#"{"src":"c1","dest":"n0","body":{"type":"read","msg_id":4}}"#)
+            .assert_msg_received_default_timeout(|msg| matches!(msg.body, Body::ReadOk(_)))
+            .run_for(gossip_interval * 10);
+        let acked = server
+            .get_messages()
+            .iter()
+            .position(|msg| matches!(msg.body, Body::ReadOk(_)))
+            .unwrap();
+        let resent: Vec<_> = server.get_messages()[acked..]
+            .iter()
+            .filter(|msg| msg.dest == "n1" && msg.body.type_name() == "gossip")
+            .collect();
+        assert!(resent.is_empty(), "kept gossiping after ack: {:?}", resent);
+    }
+}
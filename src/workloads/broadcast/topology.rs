@@ -0,0 +1,305 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    str::FromStr,
+};
+
+use crate::{rand::SplitMix64, sort_node_ids};
+
+/// How a broadcast node picks its neighbors once it receives the `topology` message.
+/// Every strategy except [`TopologyStrategy::Given`] is computed from the cluster membership,
+/// all nodes derive the same symmetric graph without talking to each other.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum TopologyStrategy {
+    /// The topology sent by Maelstrom.
+    #[default]
+    Given,
+    /// Every node is a neighbor of every other node.
+    FullMesh,
+    /// The first node is the hub, every other node only talks to the hub.
+    Star,
+    /// Breadth first spanning tree of the topology sent by Maelstrom, rooted at the first node.
+    SpanningTree,
+    /// Tree where node `i` is the parent of nodes `k * i + 1 ..= k * i + k`.
+    KaryTree(usize),
+    /// Union of `degree / 2` random hamiltonian cycles, which is connected and close to regular.
+    /// Nodes agree on the graph by using the same seed.
+    RandomRegular { degree: usize, seed: u64 },
+}
+
+impl TopologyStrategy {
+    /// Neighbors of `node_id`, `node_ids` has to be in the same order on every node.
+    pub fn neighbors(
+        &self,
+        node_id: &str,
+        node_ids: &[String],
+        given: &HashMap<String, Vec<String>>,
+    ) -> Vec<String> {
+        let graph = match self {
+            TopologyStrategy::Given => return given.get(node_id).cloned().unwrap_or_default(),
+            TopologyStrategy::FullMesh => full_mesh(node_ids.len()),
+            TopologyStrategy::Star => star(node_ids.len()),
+            TopologyStrategy::SpanningTree => return spanning_tree(node_id, node_ids, given),
+            TopologyStrategy::KaryTree(k) => kary_tree(node_ids.len(), *k),
+            TopologyStrategy::RandomRegular { degree, seed } => {
+                random_regular(node_ids.len(), *degree, *seed)
+            }
+        };
+
+        let Some(index) = node_ids.iter().position(|id| id == node_id) else {
+            return Vec::new();
+        };
+        let mut neighbors: Vec<usize> = graph[index].iter().copied().collect();
+        neighbors.sort();
+        neighbors
+            .into_iter()
+            .map(|neighbor| node_ids[neighbor].clone())
+            .collect()
+    }
+}
+
+/// Parses `given`, `full-mesh`, `star`, `spanning-tree`, `kary-tree:<k>` and
+/// `random-regular:<degree>:<seed>`, e.g. from an environment variable.
+impl FromStr for TopologyStrategy {
+    type Err = String;
+
+    fn from_str(strategy: &str) -> Result<Self, Self::Err> {
+        let number = |arg: Option<&str>| -> Result<u64, String> {
+            let arg = arg.ok_or_else(|| format!("{:?} is missing a number", strategy))?;
+            arg.parse()
+                .map_err(|err| format!("{:?} in {:?}: {}", arg, strategy, err))
+        };
+        let mut parts = strategy.trim().split(':');
+        let parsed = match parts.next().unwrap_or_default() {
+            "given" => TopologyStrategy::Given,
+            "full-mesh" => TopologyStrategy::FullMesh,
+            "star" => TopologyStrategy::Star,
+            "spanning-tree" => TopologyStrategy::SpanningTree,
+            "kary-tree" => TopologyStrategy::KaryTree(number(parts.next())? as usize),
+            "random-regular" => TopologyStrategy::RandomRegular {
+                degree: number(parts.next())? as usize,
+                seed: number(parts.next())?,
+            },
+            _ => return Err(format!("unknown topology strategy {:?}", strategy)),
+        };
+        match parts.next() {
+            Some(_) => Err(format!("too many arguments in {:?}", strategy)),
+            None => Ok(parsed),
+        }
+    }
+}
+
+type Graph = Vec<HashSet<usize>>;
+
+fn connect(graph: &mut Graph, a: usize, b: usize) {
+    if a != b {
+        graph[a].insert(b);
+        graph[b].insert(a);
+    }
+}
+
+fn full_mesh(n: usize) -> Graph {
+    let mut graph = vec![HashSet::new(); n];
+    for a in 0..n {
+        for b in a + 1..n {
+            connect(&mut graph, a, b);
+        }
+    }
+    graph
+}
+
+fn star(n: usize) -> Graph {
+    let mut graph = vec![HashSet::new(); n];
+    for node in 1..n {
+        connect(&mut graph, 0, node);
+    }
+    graph
+}
+
+fn kary_tree(n: usize, k: usize) -> Graph {
+    let mut graph = vec![HashSet::new(); n];
+    for node in 1..n {
+        connect(&mut graph, (node - 1) / k.max(1), node);
+    }
+    graph
+}
+
+fn random_regular(n: usize, degree: usize, seed: u64) -> Graph {
+    let mut graph = vec![HashSet::new(); n];
+    let mut rng = SplitMix64::from_seed(seed);
+    for _ in 0..(degree / 2).max(1) {
+        // Fisher-Yates shuffle, then connect the nodes in a cycle in the shuffled order
+        let mut order: Vec<usize> = (0..n).collect();
+        for i in (1..n).rev() {
+            let j = (rng.next() % (i as u64 + 1)) as usize;
+            order.swap(i, j);
+        }
+        for i in 0..n {
+            connect(&mut graph, order[i], order[(i + 1) % n]);
+        }
+    }
+    graph
+}
+
+fn spanning_tree(
+    node_id: &str,
+    node_ids: &[String],
+    given: &HashMap<String, Vec<String>>,
+) -> Vec<String> {
+    let Some(root) = node_ids.first() else {
+        return Vec::new();
+    };
+
+    let mut parents: HashMap<&str, &str> = HashMap::new();
+    let mut visited: HashSet<&str> = HashSet::from([root.as_str()]);
+    let mut queue = VecDeque::from([root.as_str()]);
+    while let Some(node) = queue.pop_front() {
+        // sorted so every node builds the same tree regardless of the order in the message
+        let mut edges: Vec<&str> = given
+            .get(node)
+            .into_iter()
+            .flatten()
+            .map(String::as_str)
+            .collect();
+        sort_node_ids(&mut edges);
+        for neighbor in edges {
+            if visited.insert(neighbor) {
+                parents.insert(neighbor, node);
+                queue.push_back(neighbor);
+            }
+        }
+    }
+
+    let mut neighbors: Vec<String> = parents
+        .iter()
+        .filter(|(child, parent)| **child == node_id || **parent == node_id)
+        .map(|(child, parent)| if *child == node_id { parent } else { child })
+        .map(|neighbor| neighbor.to_string())
+        .collect();
+    sort_node_ids(&mut neighbors);
+    neighbors
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, HashSet};
+
+    use super::TopologyStrategy;
+
+    fn node_ids(n: usize) -> Vec<String> {
+        (0..n).map(|i| format!("n{}", i)).collect()
+    }
+
+    fn grid() -> HashMap<String, Vec<String>> {
+        // 2x2 grid: n0 - n1
+        //            |    |
+        //           n2 - n3
+        HashMap::from([
+            ("n0".to_string(), vec!["n1".to_string(), "n2".to_string()]),
+            ("n1".to_string(), vec!["n0".to_string(), "n3".to_string()]),
+            ("n2".to_string(), vec!["n0".to_string(), "n3".to_string()]),
+            ("n3".to_string(), vec!["n1".to_string(), "n2".to_string()]),
+        ])
+    }
+
+    fn all_neighbors(
+        strategy: TopologyStrategy,
+        node_ids: &[String],
+        given: &HashMap<String, Vec<String>>,
+    ) -> HashMap<String, Vec<String>> {
+        node_ids
+            .iter()
+            .map(|node| (node.clone(), strategy.neighbors(node, node_ids, given)))
+            .collect()
+    }
+
+    /// Every edge has to be known by both ends and every node reachable from the first one.
+    fn assert_symmetric_and_connected(
+        neighbors: &HashMap<String, Vec<String>>,
+        node_ids: &[String],
+    ) {
+        for (node, adjacent) in neighbors {
+            for neighbor in adjacent {
+                assert!(
+                    neighbors[neighbor].contains(node),
+                    "{} -> {} is one sided",
+                    node,
+                    neighbor
+                );
+            }
+        }
+
+        let mut visited = HashSet::from([node_ids[0].clone()]);
+        let mut stack = vec![node_ids[0].clone()];
+        while let Some(node) = stack.pop() {
+            for neighbor in &neighbors[&node] {
+                if visited.insert(neighbor.clone()) {
+                    stack.push(neighbor.clone());
+                }
+            }
+        }
+        assert_eq!(visited.len(), node_ids.len());
+    }
+
+    #[test]
+    fn given_uses_topology_message() {
+        let neighbors = TopologyStrategy::Given.neighbors("n0", &node_ids(4), &grid());
+        assert_eq!(neighbors, vec!["n1", "n2"]);
+    }
+
+    #[test]
+    fn star_connects_everyone_to_the_hub() {
+        let node_ids = node_ids(5);
+        let neighbors = all_neighbors(TopologyStrategy::Star, &node_ids, &HashMap::new());
+        assert_eq!(neighbors["n0"], vec!["n1", "n2", "n3", "n4"]);
+        assert_eq!(neighbors["n3"], vec!["n0"]);
+        assert_symmetric_and_connected(&neighbors, &node_ids);
+    }
+
+    #[test]
+    fn kary_tree_has_k_children() {
+        let node_ids = node_ids(25);
+        let neighbors = all_neighbors(TopologyStrategy::KaryTree(4), &node_ids, &HashMap::new());
+        assert_eq!(neighbors["n0"], vec!["n1", "n2", "n3", "n4"]);
+        assert_eq!(neighbors["n1"], vec!["n0", "n5", "n6", "n7", "n8"]);
+        assert_symmetric_and_connected(&neighbors, &node_ids);
+    }
+
+    #[test]
+    fn spanning_tree_drops_cycles_of_given_topology() {
+        let node_ids = node_ids(4);
+        let neighbors = all_neighbors(TopologyStrategy::SpanningTree, &node_ids, &grid());
+        let edges: usize = neighbors.values().map(Vec::len).sum();
+        assert_eq!(edges / 2, node_ids.len() - 1);
+        assert_symmetric_and_connected(&neighbors, &node_ids);
+    }
+
+    #[test]
+    fn full_mesh_and_random_regular_are_connected() {
+        let node_ids = node_ids(25);
+        let neighbors = all_neighbors(TopologyStrategy::FullMesh, &node_ids, &HashMap::new());
+        assert!(neighbors.values().all(|adjacent| adjacent.len() == 24));
+        assert_symmetric_and_connected(&neighbors, &node_ids);
+
+        let strategy = TopologyStrategy::RandomRegular { degree: 4, seed: 7 };
+        let neighbors = all_neighbors(strategy, &node_ids, &HashMap::new());
+        assert!(
+            neighbors
+                .values()
+                .all(|adjacent| (2..=4).contains(&adjacent.len()))
+        );
+        assert_symmetric_and_connected(&neighbors, &node_ids);
+    }
+
+    #[test]
+    fn strategies_parse_from_their_names() {
+        assert_eq!("star".parse(), Ok(TopologyStrategy::Star));
+        assert_eq!("kary-tree:4".parse(), Ok(TopologyStrategy::KaryTree(4)));
+        assert_eq!(
+            "random-regular:4:7".parse(),
+            Ok(TopologyStrategy::RandomRegular { degree: 4, seed: 7 })
+        );
+        assert!("kary-tree".parse::<TopologyStrategy>().is_err());
+        assert!("star:2".parse::<TopologyStrategy>().is_err());
+        assert!("ring".parse::<TopologyStrategy>().is_err());
+    }
+}
@@ -0,0 +1,221 @@
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::rand::fnv1a;
+
+/// Order independent summary of a set of values, two sets holding the same values have the
+/// same digest.
+#[derive(Debug, Default, Serialize, Deserialize, Eq, PartialEq, Clone, Copy)]
+pub struct Digest {
+    pub count: u64,
+    pub checksum: u64,
+}
+
+impl Digest {
+    fn add(&mut self, hash: u64) {
+        self.count += 1;
+        self.checksum = self.checksum.wrapping_add(hash);
+    }
+}
+
+/// Number of buckets the values are hashed into, see [`ValueStore::missing_from`].
+pub const BUCKETS: usize = 64;
+
+/// Insertion ordered set of broadcast values with constant time lookups. Integers, by far the
+/// most common values, are indexed directly, everything else by its [`canonical`] form.
+#[derive(Debug, Default, Clone)]
+pub struct ValueStore {
+    values: Vec<Value>,
+    /// Bucket of every value, in insertion order.
+    value_buckets: Vec<usize>,
+    integers: HashSet<i64>,
+    others: HashSet<String>,
+    digest: Digest,
+    /// Digest of the values in every bucket, empty until the first value is stored.
+    buckets: Vec<Digest>,
+}
+
+impl ValueStore {
+    /// Returns `false` if the value was already stored.
+    pub fn insert(&mut self, value: Value) -> bool {
+        // a fixed hash, so nodes built separately still agree on the digest
+        let (inserted, hash) = match value.as_i64() {
+            Some(integer) => (self.integers.insert(integer), fnv1a(&integer.to_le_bytes())),
+            None => {
+                let serialized = canonical(&value);
+                let hash = fnv1a(serialized.as_bytes());
+                (self.others.insert(serialized), hash)
+            }
+        };
+        if inserted {
+            self.digest.add(hash);
+            if self.buckets.is_empty() {
+                self.buckets = vec![Digest::default(); BUCKETS];
+            }
+            let bucket = (hash % BUCKETS as u64) as usize;
+            self.buckets[bucket].add(hash);
+            self.value_buckets.push(bucket);
+            self.values.push(value);
+        }
+        inserted
+    }
+
+    pub fn contains(&self, value: &Value) -> bool {
+        match value.as_i64() {
+            Some(integer) => self.integers.contains(&integer),
+            None => self.others.contains(&canonical(value)),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    pub fn digest(&self) -> Digest {
+        self.digest
+    }
+
+    /// Digests of all [`BUCKETS`], two stores with the same values have the same buckets.
+    pub fn buckets(&self) -> Vec<Digest> {
+        if self.buckets.is_empty() {
+            vec![Digest::default(); BUCKETS]
+        } else {
+            self.buckets.clone()
+        }
+    }
+
+    /// Values of all buckets whose digest differs from the other store's `buckets`, which
+    /// includes every value the other store is missing.
+    pub fn missing_from(&self, buckets: &[Digest]) -> Vec<Value> {
+        let ours = self.buckets();
+        let differs: Vec<bool> = (0..BUCKETS)
+            .map(|bucket| buckets.get(bucket) != Some(&ours[bucket]))
+            .collect();
+        self.values
+            .iter()
+            .zip(&self.value_buckets)
+            .filter(|(_, bucket)| differs[**bucket])
+            .map(|(value, _)| value.clone())
+            .collect()
+    }
+
+    /// All values in the order they were first inserted.
+    pub fn as_slice(&self) -> &[Value] {
+        &self.values
+    }
+}
+
+/// Json with the keys of every object sorted, independent of whether serde_json keeps them in
+/// insertion order.
+fn canonical(value: &Value) -> String {
+    match value {
+        Value::Array(items) => {
+            let items: Vec<String> = items.iter().map(canonical).collect();
+            format!("[{}]", items.join(","))
+        }
+        Value::Object(fields) => {
+            let mut fields: Vec<_> = fields.iter().collect();
+            fields.sort_by_key(|(key, _)| *key);
+            let fields: Vec<String> = fields
+                .into_iter()
+                .map(|(key, value)| format!("{}:{}", Value::from(key.as_str()), canonical(value)))
+                .collect();
+            format!("{{{}}}", fields.join(","))
+        }
+        _ => value.to_string(),
+    }
+}
+
+impl FromIterator<Value> for ValueStore {
+    fn from_iter<I: IntoIterator<Item = Value>>(iter: I) -> Self {
+        let mut store = ValueStore::default();
+        for value in iter {
+            store.insert(value);
+        }
+        store
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::{ValueStore, canonical};
+
+    #[test]
+    fn keeps_first_insertion_order_without_duplicates() {
+        let mut store = ValueStore::default();
+        assert!(store.insert(json!(3)));
+        assert!(store.insert(json!("text")));
+        assert!(store.insert(json!({"b": 1, "a": [1, 2]})));
+        assert!(!store.insert(json!(3)));
+        assert!(!store.insert(json!("text")));
+        assert!(!store.insert(json!({"a": [1, 2], "b": 1})));
+        assert_eq!(
+            store.as_slice(),
+            &[json!(3), json!("text"), json!({"a": [1, 2], "b": 1})]
+        );
+    }
+
+    #[test]
+    fn distinguishes_values_that_are_not_equal_json() {
+        let store: ValueStore = [json!(1), json!(u64::MAX)].into_iter().collect();
+        assert!(store.contains(&json!(1)));
+        assert!(!store.contains(&json!(1.0)));
+        assert!(!store.contains(&json!("1")));
+        assert!(store.contains(&json!(u64::MAX)));
+        assert_eq!(store.len(), 2);
+    }
+
+    #[test]
+    fn digest_does_not_depend_on_insertion_order() {
+        let a: ValueStore = [json!(1), json!("two"), json!([3])].into_iter().collect();
+        let b: ValueStore = [json!([3]), json!(1), json!("two"), json!(1)]
+            .into_iter()
+            .collect();
+        let c: ValueStore = [json!(1), json!("two")].into_iter().collect();
+        assert_eq!(a.digest(), b.digest());
+        assert_ne!(a.digest(), c.digest());
+        assert_eq!(a.digest().count, 3);
+    }
+
+    #[test]
+    fn missing_values_come_from_differing_buckets_only() {
+        let a: ValueStore = (0..1000).map(|value| json!(value)).collect();
+        let mut b = a.clone();
+        b.insert(json!(1000));
+        assert!(a.missing_from(&a.buckets()).is_empty());
+        assert!(a.missing_from(&b.buckets()).len() < 100);
+
+        let missing = b.missing_from(&a.buckets());
+        assert!(missing.contains(&json!(1000)));
+        assert!(missing.len() < 100, "{} values", missing.len());
+        assert_eq!(b.missing_from(&[]).len(), 1001);
+    }
+
+    #[test]
+    fn canonical_form_sorts_object_keys_at_every_level() {
+        let mut nested = serde_json::Map::new();
+        nested.insert("z".to_string(), json!(1));
+        nested.insert("a".to_string(), json!({"y": [{"c": 1, "b": 2}], "x": null}));
+        assert_eq!(
+            canonical(&serde_json::Value::Object(nested)),
+            r#"{"a":{"x":null,"y":[{"b":2,"c":1}]},"z":1}"#
+        );
+    }
+
+    #[test]
+    fn digest_is_stable() {
+        let store: ValueStore = [json!(1), json!("two"), json!({"b": 1, "a": 2})]
+            .into_iter()
+            .collect();
+        // changes here break nodes built from different versions
+        assert_eq!(store.digest().checksum, 13724753685409617144);
+    }
+}
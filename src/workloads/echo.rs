@@ -1,17 +1,18 @@
 use crate::{
-    messages::{Body, Echo, EchoOk},
+    messages::{Body, Echo, EchoOk, Extended},
     router::Router,
 };
 
+/// Replies with the same text, fields of the request unknown to [`Echo`] are echoed as well.
 pub fn insert_echo_handlers<U>(router: &mut Router<U>) {
-    router.on(|echo: Echo, tx, src, maelstrom, _| {
-        let body = Body::EchoOk(EchoOk {
+    router.on(|echo: Extended<Echo>, ctx, _| {
+        let body = Body::encode(&echo.with(EchoOk {
             msg_id: None,
-            in_reply_to: echo.msg_id,
-            echo: echo.echo,
-        });
-        let msg = maelstrom.create_message(src, body);
-        tx.send(msg).unwrap();
+            in_reply_to: echo.message.msg_id,
+            echo: echo.message.echo.clone(),
+        }))?;
+        ctx.reply(body);
+        Ok(())
     });
 }
 
@@ -34,4 +35,16 @@ mod tests {
                 false
             });
     }
+
+    #[test]
+    fn should_echo_unknown_fields_back() {
+        let mut router = create_router::<()>();
+        insert_echo_handlers(&mut router);
+        testing::TestServer::from_router(router)
+            .send_str(r#"{"src":"c1","dest":"n1","body":{"type":"echo","msg_id":1,"echo":"hi","client":"x"}}"#)
+            .assert_msg_received_default_timeout(|msg| {
+                msg.body.in_reply_to() == Some(1)
+                    && matches!(&msg.body, Body::Custom(body) if body["client"] == "x" && body["echo"] == "hi")
+            });
+    }
 }
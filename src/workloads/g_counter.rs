@@ -0,0 +1,157 @@
+use std::{collections::HashMap, sync::mpsc::Sender, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    Maelstrom, error,
+    messages::{Add, AddOk, Body, CounterReadOk, Message, MessageType, Read},
+    router::{Context, HandlerResult, Router},
+};
+
+const GOSSIP_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Grow-only counter as a state based CRDT: every node only increments its own slot and
+/// periodically sends all slots to the other nodes, which merge them by taking the maximum.
+#[derive(Debug, Default)]
+pub struct GCounter {
+    counters: HashMap<String, u64>,
+}
+
+impl GCounter {
+    fn value(&self) -> u64 {
+        self.counters.values().sum()
+    }
+
+    fn merge(&mut self, counters: HashMap<String, u64>) {
+        for (node, value) in counters {
+            let counter = self.counters.entry(node).or_default();
+            *counter = value.max(*counter);
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
+struct CounterGossip {
+    counters: HashMap<String, u64>,
+}
+
+impl MessageType for CounterGossip {
+    const TYPE: &'static str = "counter_gossip";
+}
+
+fn gossip(tx: &mut Sender<Message>, maelstrom: &mut Maelstrom<GCounter>, data: &mut GCounter) {
+    let body = match Body::encode(&CounterGossip {
+        counters: data.counters.clone(),
+    }) {
+        Ok(body) => body,
+        Err(err) => {
+            error!("Could not encode gossip: {}", err);
+            return;
+        }
+    };
+    for node in maelstrom.other_node_ids() {
+        // only fails once the server stopped
+        let _ = tx.send(maelstrom.create_message(node, body.clone()));
+    }
+}
+
+fn add(add: Add, ctx: &mut Context<GCounter>, data: &mut GCounter) -> HandlerResult {
+    *data.counters.entry(ctx.node_id().to_string()).or_default() += add.delta;
+
+    let body = Body::AddOk(AddOk {
+        msg_id: None,
+        in_reply_to: add.msg_id,
+    });
+    ctx.reply(body);
+    Ok(())
+}
+
+fn read(read: Read, ctx: &mut Context<GCounter>, data: &mut GCounter) -> HandlerResult {
+    let body = Body::encode(&CounterReadOk {
+        msg_id: None,
+        in_reply_to: read.msg_id,
+        value: data.value(),
+    })?;
+    ctx.reply(body);
+    Ok(())
+}
+
+fn counter_gossip(
+    gossip: CounterGossip,
+    _ctx: &mut Context<GCounter>,
+    data: &mut GCounter,
+) -> HandlerResult {
+    data.merge(gossip.counters);
+    Ok(())
+}
+
+pub fn insert_g_counter_handlers(router: &mut Router<GCounter>) {
+    router.every(GOSSIP_INTERVAL, gossip);
+
+    router.on(add);
+    router.on(read);
+    router.on(counter_gossip);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        messages::{Body, CounterReadOk},
+        testing,
+        workloads::init::create_router,
+    };
+
+    use super::{CounterGossip, GCounter, insert_g_counter_handlers};
+
+    fn read_value(body: &Body) -> Option<u64> {
+        match body.decode::<CounterReadOk>() {
+            Ok(read_ok) if body.type_name() == "read_ok" => Some(read_ok.value),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn should_sum_added_deltas_on_read() {
+        let mut router = create_router::<GCounter>();
+        insert_g_counter_handlers(&mut router);
+        testing::TestServer::from_router(router)
+            .send_str(r#"{"src":"c0","dest":"n0","body":{"type":"init","node_id":"n0","node_ids":["n0"],"msg_id":1}}"#)
+            .send_str(r#"{"src":"c1","dest":"n0","body":{"type":"add","delta":3,"msg_id":2}}"#)
+            .send_str(r#"{"src":"c1","dest":"n0","body":{"type":"add","delta":4,"msg_id":3}}"#)
+            .assert_msg_received_default_timeout(|msg| msg.body.in_reply_to() == Some(3))
+            .send_str(r#"{"src":"c1","dest":"n0","body":{"type":"read","msg_id":4}}"#)
+            .assert_msg_received_default_timeout(|msg| read_value(&msg.body) == Some(7));
+    }
+
+    #[test]
+    fn should_merge_gossip_from_other_nodes() {
+        let mut router = create_router::<GCounter>();
+        insert_g_counter_handlers(&mut router);
+        testing::TestServer::from_router(router)
+            .send_str(r#"{"src":"c0","dest":"n0","body":{"type":"init","node_id":"n0","node_ids":["n0","n1"],"msg_id":1}}"#)
+            .send_str(r#"{"src":"c1","dest":"n0","body":{"type":"add","delta":1,"msg_id":2}}"#)
+            .send_str(r#"{"src":"n1","dest":"n0","body":{"type":"counter_gossip","counters":{"n1":5}}}"#)
+            .send_str(r#"{"src":"n1","dest":"n0","body":{"type":"counter_gossip","counters":{"n1":2}}}"#)
+            .send_str(r#"{"src":"c1","dest":"n0","body":{"type":"read","msg_id":3}}"#)
+            .assert_msg_received_default_timeout(|msg| read_value(&msg.body) == Some(6));
+    }
+
+    #[test]
+    fn should_gossip_counters_to_other_nodes() {
+        let mut router = create_router::<GCounter>();
+        insert_g_counter_handlers(&mut router);
+        testing::TestServer::from_router(router)
+            .send_str(r#"{"src":"c0","dest":"n0","body":{"type":"init","node_id":"n0","node_ids":["n0","n1"],"msg_id":1}}"#)
+            .send_str(r#"{"src":"c1","dest":"n0","body":{"type":"add","delta":2,"msg_id":2}}"#)
+            .assert_msg_received_timeout(
+                |msg| {
+                    msg.dest == "n1"
+                        && msg
+                            .body
+                            .decode::<CounterGossip>()
+                            .is_ok_and(|gossip| gossip.counters.get("n0") == Some(&2))
+                },
+                std::time::Duration::from_secs(1),
+            );
+    }
+}
@@ -4,13 +4,13 @@ use crate::{
 };
 
 pub fn insert_handlers<U>(router: &mut Router<U>) {
-    router.on(|init: Init, tx, src, maelstrom, _| {
-        maelstrom.node_id = init.node_id;
+    router.on(|init: Init, ctx, _| {
+        ctx.init(init.node_id, init.node_ids);
         let body = Body::InitOk(InitOk {
             in_reply_to: init.msg_id,
         });
-        let msg = maelstrom.create_message(src, body);
-        tx.send(msg).unwrap();
+        ctx.reply(body);
+        Ok(())
     });
 }
 
@@ -22,7 +22,10 @@ pub fn create_router<U: Default>() -> Router<U> {
 
 #[cfg(test)]
 mod tests {
-    use crate::{messages::Body, testing};
+    use crate::{
+        messages::{Body, Echo, EchoOk},
+        testing,
+    };
 
     use super::create_router;
 
@@ -35,4 +38,31 @@ mod tests {
                 matches!(msg.body, Body::InitOk(_))
             });
     }
+
+    #[test]
+    fn should_store_cluster_membership() {
+        let mut router = create_router::<()>();
+        router.on(|echo: Echo, ctx, _| {
+            let body = Body::EchoOk(EchoOk {
+                msg_id: None,
+                in_reply_to: echo.msg_id,
+                echo: format!(
+                    "{} {:?} {:?} {:?}",
+                    ctx.node_id(),
+                    ctx.node_index(),
+                    ctx.node_ids(),
+                    ctx.other_node_ids().collect::<Vec<_>>(),
+                ),
+            });
+            ctx.reply(body);
+            Ok(())
+        });
+        testing::TestServer::from_router(router)
+            .send_str(r#"{"src":"c0","dest":"n2","body":{"type":"init","node_id":"n2","node_ids":["n10","n2","n1"],"msg_id":1}}"#)
+            .send_str(r#"{"src":"c1","dest":"n2","body":{"type":"echo","msg_id":2,"echo":""}}"#)
+            .assert_msg_received_default_timeout(|msg| {
+                matches!(&msg.body, Body::EchoOk(echo_ok)
+                    if echo_ok.echo == r#"n2 Some(1) ["n1", "n2", "n10"] ["n1", "n10"]"#)
+            });
+    }
 }
@@ -0,0 +1,371 @@
+use std::{collections::HashMap, time::Duration};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    messages::{Body, Extended, MessageType},
+    router::{Context, HandlerResult, Router},
+    rpc::RpcOptions,
+};
+
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
+pub struct SendMsg {
+    pub msg_id: u64,
+    pub key: String,
+    pub msg: Value,
+}
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
+pub struct SendOk {
+    pub in_reply_to: u64,
+    pub offset: u64,
+}
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
+pub struct Poll {
+    pub msg_id: u64,
+    pub offsets: HashMap<String, u64>,
+}
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
+pub struct PollOk {
+    pub in_reply_to: u64,
+    pub msgs: HashMap<String, Vec<(u64, Value)>>,
+}
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
+pub struct CommitOffsets {
+    pub msg_id: u64,
+    pub offsets: HashMap<String, u64>,
+}
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
+pub struct CommitOffsetsOk {
+    pub in_reply_to: u64,
+}
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
+pub struct ListCommittedOffsets {
+    pub msg_id: u64,
+    pub keys: Vec<String>,
+}
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
+pub struct ListCommittedOffsetsOk {
+    pub in_reply_to: u64,
+    pub offsets: HashMap<String, u64>,
+}
+
+impl MessageType for SendMsg {
+    const TYPE: &'static str = "send";
+}
+impl MessageType for SendOk {
+    const TYPE: &'static str = "send_ok";
+}
+impl MessageType for Poll {
+    const TYPE: &'static str = "poll";
+}
+impl MessageType for PollOk {
+    const TYPE: &'static str = "poll_ok";
+}
+impl MessageType for CommitOffsets {
+    const TYPE: &'static str = "commit_offsets";
+}
+impl MessageType for CommitOffsetsOk {
+    const TYPE: &'static str = "commit_offsets_ok";
+}
+impl MessageType for ListCommittedOffsets {
+    const TYPE: &'static str = "list_committed_offsets";
+}
+impl MessageType for ListCommittedOffsetsOk {
+    const TYPE: &'static str = "list_committed_offsets_ok";
+}
+
+/// Maximum number of messages returned per key on a single poll.
+const POLL_LIMIT: usize = 100;
+
+/// Forwards are resent until the leader answers, but given up before Maelstrom's client
+/// timeout of 5s, so the client gets the error instead of a timeout.
+const FORWARD_OPTIONS: RpcOptions = RpcOptions {
+    timeout: Duration::from_secs(4),
+    retry_interval: Some(Duration::from_millis(250)),
+};
+
+#[derive(Debug, Default)]
+pub struct Kafka {
+    /// The offset of a message is its index in the log of its key.
+    logs: HashMap<String, Vec<Value>>,
+    committed: HashMap<String, u64>,
+    /// Offset of every `send` by sender and `msg_id`. A forwarded `send` is resent until the
+    /// leader's reply arrives and must not be appended again.
+    sent: HashMap<(String, u64), u64>,
+}
+
+impl Kafka {
+    fn append(&mut self, key: String, msg: Value) -> u64 {
+        let log = self.logs.entry(key).or_default();
+        log.push(msg);
+        log.len() as u64 - 1
+    }
+
+    fn poll(&self, key: &str, offset: u64) -> Vec<(u64, Value)> {
+        let Some(log) = self.logs.get(key) else {
+            return Vec::new();
+        };
+        log.iter()
+            .enumerate()
+            .skip(offset as usize)
+            .take(POLL_LIMIT)
+            .map(|(offset, msg)| (offset as u64, msg.clone()))
+            .collect()
+    }
+
+    fn commit(&mut self, key: String, offset: u64) {
+        let committed = self.committed.entry(key).or_default();
+        *committed = offset.max(*committed);
+    }
+}
+
+fn send_msg(send: SendMsg, ctx: &mut Context<Kafka>, data: &mut Kafka) -> HandlerResult {
+    let offset = match data.sent.get(&(ctx.src().to_string(), send.msg_id)) {
+        Some(offset) => *offset,
+        None => {
+            let offset = data.append(send.key, send.msg);
+            data.sent
+                .insert((ctx.src().to_string(), send.msg_id), offset);
+            offset
+        }
+    };
+    let body = Body::encode(&SendOk {
+        in_reply_to: send.msg_id,
+        offset,
+    })?;
+    ctx.reply(body);
+    Ok(())
+}
+
+fn poll(poll: Poll, ctx: &mut Context<Kafka>, data: &mut Kafka) -> HandlerResult {
+    let msgs = poll
+        .offsets
+        .into_iter()
+        .map(|(key, offset)| {
+            let msgs = data.poll(&key, offset);
+            (key, msgs)
+        })
+        .collect();
+    let body = Body::encode(&PollOk {
+        in_reply_to: poll.msg_id,
+        msgs,
+    })?;
+    ctx.reply(body);
+    Ok(())
+}
+
+fn commit_offsets(
+    commit_offsets: CommitOffsets,
+    ctx: &mut Context<Kafka>,
+    data: &mut Kafka,
+) -> HandlerResult {
+    for (key, offset) in commit_offsets.offsets {
+        data.commit(key, offset);
+    }
+    let body = Body::encode(&CommitOffsetsOk {
+        in_reply_to: commit_offsets.msg_id,
+    })?;
+    ctx.reply(body);
+    Ok(())
+}
+
+fn list_committed_offsets(
+    list: ListCommittedOffsets,
+    ctx: &mut Context<Kafka>,
+    data: &mut Kafka,
+) -> HandlerResult {
+    let offsets = list
+        .keys
+        .into_iter()
+        .filter_map(|key| {
+            let offset = *data.committed.get(&key)?;
+            Some((key, offset))
+        })
+        .collect();
+    let body = Body::encode(&ListCommittedOffsetsOk {
+        in_reply_to: list.msg_id,
+        offsets,
+    })?;
+    ctx.reply(body);
+    Ok(())
+}
+
+/// Wraps a handler so that it only runs on the leader, the first node of the cluster. Every
+/// other node forwards the request to the leader and relays the reply back to the client.
+/// Fields of the request unknown to `M` are forwarded to the leader, but `handler` never sees
+/// them, on the leader or elsewhere, so the reply is the same whichever node the client asked.
+fn on_leader<M, F>(
+    handler: F,
+) -> impl Fn(Extended<M>, &mut Context<Kafka>, &mut Kafka) -> HandlerResult + Send + 'static
+where
+    M: MessageType,
+    F: Fn(M, &mut Context<Kafka>, &mut Kafka) -> HandlerResult + Send + 'static,
+{
+    move |request: Extended<M>, ctx, data| {
+        let leader = match ctx.node_ids().first() {
+            Some(leader) if leader != ctx.node_id() => leader.clone(),
+            _ => return handler(request.message, ctx, data),
+        };
+
+        // forwarded as received, including fields we don't know about
+        let body = Body::encode(&request)?;
+        let reply_to = ctx.reply_to();
+        ctx.rpc(
+            &leader,
+            body,
+            FORWARD_OPTIONS,
+            move |result, tx, maelstrom, _| {
+                reply_to.reply(tx, maelstrom, result.unwrap_or_else(Body::Error));
+            },
+        );
+        Ok(())
+    }
+}
+
+/// Every node serves its own log, only correct when running a single node.
+pub fn insert_kafka_handlers(router: &mut Router<Kafka>) {
+    router.on(send_msg);
+    router.on(poll);
+    router.on(commit_offsets);
+    router.on(list_committed_offsets);
+}
+
+/// The first node serves the log and all other nodes forward to it.
+pub fn insert_replicated_kafka_handlers(router: &mut Router<Kafka>) {
+    router.on(on_leader(send_msg));
+    router.on(on_leader(poll));
+    router.on(on_leader(commit_offsets));
+    router.on(on_leader(list_committed_offsets));
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, time::Duration};
+
+    use serde_json::json;
+
+    use crate::{messages::Body, testing, workloads::init::create_router};
+
+    use super::{
+        Kafka, ListCommittedOffsetsOk, PollOk, SendOk, insert_kafka_handlers,
+        insert_replicated_kafka_handlers,
+    };
+
+    #[test]
+    fn should_assign_increasing_offsets_per_key() {
+        let mut router = create_router::<Kafka>();
+        insert_kafka_handlers(&mut router);
+        testing::TestServer::from_router(router)
+            .send_str(r#"{"src":"c1","dest":"n0","body":{"type":"send","key":"k1","msg":10,"msg_id":1}}"#)
+            .send_str(r#"{"src":"c1","dest":"n0","body":{"type":"send","key":"k2","msg":20,"msg_id":2}}"#)
+            .send_str(r#"{"src":"c1","dest":"n0","body":{"type":"send","key":"k1","msg":11,"msg_id":3}}"#)
+            .assert_msg_received_default_timeout(|msg| {
+                msg.body
+                    .decode::<SendOk>()
+                    .is_ok_and(|send_ok| send_ok.in_reply_to == 3 && send_ok.offset == 1)
+            })
+            .send_str(r#"{"src":"c1","dest":"n0","body":{"type":"poll","offsets":{"k1":1,"k2":0},"msg_id":4}}"#)
+            .assert_msg_received_default_timeout(|msg| {
+                msg.body.decode::<PollOk>().is_ok_and(|poll_ok| {
+                    poll_ok.msgs["k1"] == vec![(1, json!(11))]
+                        && poll_ok.msgs["k2"] == vec![(0, json!(20))]
+                })
+            });
+    }
+
+    #[test]
+    fn should_list_committed_offsets() {
+        let mut router = create_router::<Kafka>();
+        insert_kafka_handlers(&mut router);
+        testing::TestServer::from_router(router)
+            .send_str(r#"{"src":"c1","dest":"n0","body":{"type":"commit_offsets","offsets":{"k1":3},"msg_id":1}}"#)
+            .send_str(r#"{"src":"c1","dest":"n0","body":{"type":"commit_offsets","offsets":{"k1":2},"msg_id":2}}"#)
+            .send_str(r#"{"src":"c1","dest":"n0","body":{"type":"list_committed_offsets","keys":["k1","k2"],"msg_id":3}}"#)
+            .assert_msg_received_default_timeout(|msg| {
+                msg.body
+                    .decode::<ListCommittedOffsetsOk>()
+                    .is_ok_and(|list_ok| list_ok.offsets.len() == 1 && list_ok.offsets["k1"] == 3)
+            });
+    }
+
+    #[test]
+    fn should_forward_requests_to_leader() {
+        let mut router = create_router::<Kafka>();
+        insert_replicated_kafka_handlers(&mut router);
+        testing::TestServer::from_router(router)
+            .send_str(r#"{"src":"c0","dest":"n1","body":{"type":"init","node_id":"n1","node_ids":["n0","n1"],"msg_id":1}}"#)
+            .send_str(r#"{"src":"c1","dest":"n1","body":{"type":"send","key":"k1","msg":10,"msg_id":7,"trace":"t1"}}"#)
+            .assert_msg_received_default_timeout(|msg| {
+                msg.dest == "n0"
+                    && msg.body.type_name() == "send"
+                    && msg.body.msg_id() == Some(0)
+                    && matches!(&msg.body, Body::Custom(body) if body["trace"] == "t1")
+            })
+            .send_str(r#"{"src":"n0","dest":"n1","body":{"type":"send_ok","offset":5,"in_reply_to":0}}"#)
+            .assert_msg_received_default_timeout(|msg| {
+                msg.dest == "c1"
+                    && msg
+                        .body
+                        .decode::<SendOk>()
+                        .is_ok_and(|send_ok| send_ok.in_reply_to == 7 && send_ok.offset == 5)
+            });
+    }
+
+    #[test]
+    fn leader_should_serve_requests_with_unknown_fields() {
+        let mut router = create_router::<Kafka>();
+        insert_replicated_kafka_handlers(&mut router);
+        testing::TestServer::from_router(router)
+            .send_str(r#"{"src":"c0","dest":"n0","body":{"type":"init","node_id":"n0","node_ids":["n0","n1"],"msg_id":1}}"#)
+            .send_str(r#"{"src":"n1","dest":"n0","body":{"type":"send","key":"k1","msg":10,"msg_id":0,"trace":"t1"}}"#)
+            .assert_msg_received_default_timeout(|msg| {
+                msg.dest == "n1"
+                    && msg.body.decode::<SendOk>().is_ok_and(|send_ok| {
+                        send_ok.in_reply_to == 0 && send_ok.offset == 0
+                    })
+                    && matches!(&msg.body, Body::Custom(body) if body.get("trace").is_none())
+            });
+    }
+
+    #[test]
+    fn should_resend_forwarded_requests_until_leader_replies() {
+        let mut router = create_router::<Kafka>();
+        insert_replicated_kafka_handlers(&mut router);
+        let forwards = Cell::new(0);
+        testing::TestServer::from_router(router)
+            .send_str(r#"{"src":"c0","dest":"n1","body":{"type":"init","node_id":"n1","node_ids":["n0","n1"],"msg_id":1}}"#)
+            .send_str(r#"{"src":"c1","dest":"n1","body":{"type":"send","key":"k1","msg":10,"msg_id":7}}"#)
+            .assert_msg_received_timeout(
+                |msg| {
+                    if msg.dest == "n0" && msg.body.type_name() == "send" {
+                        forwards.set(forwards.get() + 1);
+                    }
+                    forwards.get() == 2
+                },
+                Duration::from_secs(1),
+            );
+    }
+
+    #[test]
+    fn leader_should_append_resent_send_once() {
+        let mut router = create_router::<Kafka>();
+        insert_replicated_kafka_handlers(&mut router);
+        testing::TestServer::from_router(router)
+            .send_str(r#"{"src":"c0","dest":"n0","body":{"type":"init","node_id":"n0","node_ids":["n0","n1"],"msg_id":1}}"#)
+            .send_str(r#"{"src":"n1","dest":"n0","body":{"type":"send","key":"k1","msg":10,"msg_id":3}}"#)
+            .send_str(r#"{"src":"n1","dest":"n0","body":{"type":"send","key":"k1","msg":10,"msg_id":3}}"#)
+            .send_str(r#"{"src":"n1","dest":"n0","body":{"type":"send","key":"k1","msg":11,"msg_id":4}}"#)
+            .assert_msg_received_default_timeout(|msg| {
+                msg.body
+                    .decode::<SendOk>()
+                    .is_ok_and(|send_ok| send_ok.in_reply_to == 4 && send_ok.offset == 1)
+            })
+            .send_str(r#"{"src":"c1","dest":"n0","body":{"type":"poll","offsets":{"k1":0},"msg_id":5}}"#)
+            .assert_msg_received_default_timeout(|msg| {
+                msg.body.decode::<PollOk>().is_ok_and(|poll_ok| {
+                    poll_ok.msgs["k1"] == vec![(0, json!(10)), (1, json!(11))]
+                })
+            });
+    }
+}
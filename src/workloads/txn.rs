@@ -0,0 +1,441 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::mpsc::Sender,
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    Maelstrom, error,
+    messages::{Body, ErrorCode, Message, MessageType},
+    router::{Context, HandlerError, HandlerResult, Router},
+    rpc::RpcOptions,
+    warn,
+};
+
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone, Copy)]
+pub enum OpKind {
+    #[serde(rename = "r")]
+    Read,
+    #[serde(rename = "w")]
+    Write,
+}
+
+/// A micro-operation `["r", key, null]` or `["w", key, value]`, reads are answered by
+/// filling in the value.
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
+pub struct Op(pub OpKind, pub u64, pub Option<u64>);
+
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
+pub struct TxnRequest {
+    pub msg_id: u64,
+    pub txn: Vec<Op>,
+}
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
+pub struct TxnOk {
+    pub in_reply_to: u64,
+    pub txn: Vec<Op>,
+}
+/// Writes of one transaction, or a single write under read uncommitted, and their clock.
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
+struct Replication {
+    clock: u64,
+    writes: Vec<(u64, u64)>,
+}
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
+struct TxnReplicate {
+    msg_id: u64,
+    replications: Vec<Replication>,
+}
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
+struct TxnReplicateOk {
+    in_reply_to: u64,
+}
+
+impl MessageType for TxnRequest {
+    const TYPE: &'static str = "txn";
+}
+impl MessageType for TxnOk {
+    const TYPE: &'static str = "txn_ok";
+}
+impl MessageType for TxnReplicate {
+    const TYPE: &'static str = "txn_replicate";
+}
+impl MessageType for TxnReplicateOk {
+    const TYPE: &'static str = "txn_replicate_ok";
+}
+
+const REPLICATION_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Not resent by the rpc, the next round sends everything still pending once it timed out.
+const REPLICATION_OPTIONS: RpcOptions = RpcOptions {
+    timeout: Duration::from_secs(1),
+    retry_interval: None,
+};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Isolation {
+    /// Every write is replicated on its own as soon as it is applied.
+    ReadUncommitted,
+    /// Only the final value of each key is replicated, all writes of a transaction together,
+    /// so other nodes never observe intermediate or partial transactions.
+    #[default]
+    ReadCommitted,
+}
+
+/// Lamport clock and node of the write, used to order writes the same way on every node.
+type Version = (u64, String);
+
+/// Totally available key/value store: transactions are executed locally and their writes
+/// replicated to all other nodes, which apply them last-writer-wins by [`Version`].
+#[derive(Debug, Default)]
+pub struct Txn {
+    isolation: Isolation,
+    clock: u64,
+    store: HashMap<u64, (Version, u64)>,
+    /// Replications not yet acknowledged by each other node, in the order they were made.
+    pending: HashMap<String, Vec<Replication>>,
+    /// Nodes with a `txn_replicate` in flight, at most one per node.
+    in_flight: HashSet<String>,
+}
+
+impl Txn {
+    pub fn new(isolation: Isolation) -> Self {
+        Self {
+            isolation,
+            ..Default::default()
+        }
+    }
+
+    fn apply(&mut self, key: u64, version: Version, value: u64) {
+        match self.store.get(&key) {
+            Some((current, _)) if *current >= version => {}
+            _ => {
+                self.store.insert(key, (version, value));
+            }
+        }
+    }
+}
+
+/// Sends every other node all replications it has not acknowledged yet in one message. A node
+/// that does not answer gets one message per rpc timeout, however many transactions are made.
+fn replicate_round(tx: &mut Sender<Message>, maelstrom: &mut Maelstrom<Txn>, data: &mut Txn) {
+    for (node, pending) in &data.pending {
+        if pending.is_empty() || data.in_flight.contains(node) {
+            continue;
+        }
+        // msg_id is assigned by the rpc
+        let body = match Body::encode(&TxnReplicate {
+            msg_id: 0,
+            replications: pending.clone(),
+        }) {
+            Ok(body) => body,
+            Err(err) => {
+                error!("Could not encode replications: {}", err);
+                continue;
+            }
+        };
+        data.in_flight.insert(node.clone());
+        let sent = pending.len();
+        let peer = node.clone();
+        maelstrom.rpc(
+            tx,
+            node,
+            body,
+            REPLICATION_OPTIONS,
+            move |result, _, _, data| {
+                data.in_flight.remove(&peer);
+                match result {
+                    // only appended to while in flight, the first `sent` are the ones acknowledged
+                    Ok(_) => {
+                        if let Some(pending) = data.pending.get_mut(&peer) {
+                            pending.drain(..sent);
+                        }
+                    }
+                    Err(error) => warn!("Could not replicate writes to {}: {:?}", peer, error),
+                }
+            },
+        );
+    }
+}
+
+fn txn(request: TxnRequest, ctx: &mut Context<Txn>, data: &mut Txn) -> HandlerResult {
+    // rejected before anything is applied, the transaction must not be partially executed
+    if let Some(Op(_, key, _)) = request
+        .txn
+        .iter()
+        .find(|Op(kind, _, value)| *kind == OpKind::Write && value.is_none())
+    {
+        return Err(HandlerError::new(
+            ErrorCode::MalformedRequest,
+            format!("write of key {} has no value", key),
+        ));
+    }
+
+    let node_id = ctx.node_id().to_string();
+    let mut writes = Vec::new();
+    let mut replications = Vec::new();
+    data.clock += 1;
+
+    let mut ops = request.txn;
+    for Op(kind, key, value) in ops.iter_mut() {
+        match (kind, *value) {
+            (OpKind::Read, _) => *value = data.store.get(key).map(|(_, value)| *value),
+            // rejected above
+            (OpKind::Write, None) => {}
+            (OpKind::Write, Some(value)) => {
+                if data.isolation == Isolation::ReadUncommitted {
+                    data.clock += 1;
+                    replications.push(Replication {
+                        clock: data.clock,
+                        writes: vec![(*key, value)],
+                    });
+                } else {
+                    writes.retain(|(written, _)| written != key);
+                    writes.push((*key, value));
+                }
+                // the clock is ahead of every version seen so far, so local writes always win
+                data.store
+                    .insert(*key, ((data.clock, node_id.clone()), value));
+            }
+        }
+    }
+    if !writes.is_empty() {
+        replications.push(Replication {
+            clock: data.clock,
+            writes,
+        });
+    }
+
+    let body = Body::encode(&TxnOk {
+        in_reply_to: request.msg_id,
+        txn: ops,
+    })?;
+    ctx.reply(body);
+
+    for node in ctx.other_node_ids() {
+        data.pending
+            .entry(node.to_string())
+            .or_default()
+            .extend(replications.iter().cloned());
+    }
+    Ok(())
+}
+
+fn txn_replicate(replicate: TxnReplicate, ctx: &mut Context<Txn>, data: &mut Txn) -> HandlerResult {
+    // all in one handler, so whole transactions become visible at once
+    for Replication { clock, writes } in replicate.replications {
+        data.clock = data.clock.max(clock);
+        for (key, value) in writes {
+            data.apply(key, (clock, ctx.src().to_string()), value);
+        }
+    }
+
+    let body = Body::encode(&TxnReplicateOk {
+        in_reply_to: replicate.msg_id,
+    })?;
+    ctx.reply(body);
+    Ok(())
+}
+
+pub fn insert_txn_handlers(router: &mut Router<Txn>) {
+    router.on(txn);
+    router.on(txn_replicate);
+    router.every(REPLICATION_INTERVAL, replicate_round);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{
+        messages::{Body, ErrorCode, Message},
+        testing,
+        workloads::init::create_router,
+    };
+
+    use super::{
+        Isolation, Op, OpKind, REPLICATION_INTERVAL, Txn, TxnOk, TxnReplicate, TxnRequest,
+        insert_txn_handlers,
+    };
+
+    /// Writes of every replication in a `txn_replicate`, empty for any other message.
+    fn replicated_writes(msg: &Message) -> Vec<Vec<(u64, u64)>> {
+        msg.body
+            .decode::<TxnReplicate>()
+            .map(|replicate| {
+                replicate
+                    .replications
+                    .into_iter()
+                    .map(|replication| replication.writes)
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn should_read_own_writes() {
+        let mut router = create_router::<Txn>();
+        insert_txn_handlers(&mut router);
+        testing::TestServer::from_router(router)
+            .send_str(r#"{"src":"c0","dest":"n0","body":{"type":"init","node_id":"n0","node_ids":["n0"],"msg_id":1}}"#)
+            .send_str(r#"{"src":"c1","dest":"n0","body":{"type":"txn","txn":[["r",1,null],["w",1,5],["w",1,6],["r",1,null]],"msg_id":2}}"#)
+            .assert_msg_received_default_timeout(|msg| {
+                msg.body.decode::<TxnOk>().is_ok_and(|txn_ok| {
+                    txn_ok.txn
+                        == vec![
+                            Op(OpKind::Read, 1, None),
+                            Op(OpKind::Write, 1, Some(5)),
+                            Op(OpKind::Write, 1, Some(6)),
+                            Op(OpKind::Read, 1, Some(6)),
+                        ]
+                })
+            });
+    }
+
+    #[test]
+    fn should_replicate_final_writes_to_other_nodes() {
+        let mut router = create_router::<Txn>();
+        insert_txn_handlers(&mut router);
+        testing::TestServer::from_router(router)
+            .send_str(r#"{"src":"c0","dest":"n0","body":{"type":"init","node_id":"n0","node_ids":["n0","n1"],"msg_id":1}}"#)
+            .send_str(r#"{"src":"c1","dest":"n0","body":{"type":"txn","txn":[["w",1,6],["w",2,7],["w",1,8]],"msg_id":2}}"#)
+            .assert_msg_received_default_timeout(|msg| {
+                msg.dest == "n1"
+                    && replicated_writes(msg) == vec![vec![(2, 7), (1, 8)]]
+            });
+    }
+
+    #[test]
+    fn should_batch_replications_while_waiting_for_ack() {
+        let mut router = create_router::<Txn>();
+        insert_txn_handlers(&mut router);
+        let to_n1 = |msg: &&Message| msg.dest == "n1" && msg.body.type_name() == "txn_replicate";
+        let server = testing::TestServer::from_router(router)
+            .send_str(r#"{"src":"c0","dest":"n0","body":{"type":"init","node_id":"n0","node_ids":["n0","n1"],"msg_id":1}}"#)
+            .send_str(r#"{"src":"c1","dest":"n0","body":{"type":"txn","txn":[["w",1,1]],"msg_id":2}}"#)
+            .assert_msg_received_default_timeout(|msg| replicated_writes(msg) == vec![vec![(1, 1)]])
+            .send_str(r#"{"src":"c1","dest":"n0","body":{"type":"txn","txn":[["w",2,2]],"msg_id":3}}"#)
+            .send_str(r#"{"src":"c1","dest":"n0","body":{"type":"txn","txn":[["w",3,3]],"msg_id":4}}"#)
+            .run_for(REPLICATION_INTERVAL * 6);
+        let sent: Vec<_> = server.get_messages().iter().filter(to_n1).collect();
+        assert_eq!(sent.len(), 1, "sent while waiting for the ack: {:?}", sent);
+
+        let msg_id = sent[0].body.msg_id().unwrap();
+        server
+            .send_str(&format!(
+                r#"{{"src":"n1","dest":"n0","body":{{"type":"txn_replicate_ok","in_reply_to":{}}}}}"#,
+                msg_id
+            ))
+            .assert_msg_received_default_timeout(|msg| {
+                replicated_writes(msg) == vec![vec![(2, 2)], vec![(3, 3)]]
+            });
+    }
+
+    #[test]
+    fn should_replicate_intermediate_writes_only_when_read_uncommitted() {
+        let intermediate = |msg: &Message| replicated_writes(msg).contains(&vec![(1, 5)]);
+        let init = r#"{"src":"c0","dest":"n0","body":{"type":"init","node_id":"n0","node_ids":["n0","n1"],"msg_id":1}}"#;
+        let txn = r#"{"src":"c1","dest":"n0","body":{"type":"txn","txn":[["w",1,5],["w",1,6]],"msg_id":2}}"#;
+
+        let mut router = create_router::<Txn>();
+        insert_txn_handlers(&mut router);
+        testing::TestServer::from_router_with_data(router, Txn::new(Isolation::ReadUncommitted))
+            .send_str(init)
+            .send_str(txn)
+            .assert_msg_received_default_timeout(intermediate);
+
+        let mut router = create_router::<Txn>();
+        insert_txn_handlers(&mut router);
+        let server =
+            testing::TestServer::from_router_with_data(router, Txn::new(Isolation::ReadCommitted))
+                .send_str(init)
+                .send_str(txn)
+                .wait_for_messages();
+        let replicated = |writes: Vec<(u64, u64)>| {
+            server
+                .get_messages()
+                .iter()
+                .any(|msg| replicated_writes(msg).contains(&writes))
+        };
+        assert!(replicated(vec![(1, 6)]));
+        assert!(!replicated(vec![(1, 5)]));
+    }
+
+    #[test]
+    fn should_reject_writes_without_value() {
+        let mut router = create_router::<Txn>();
+        insert_txn_handlers(&mut router);
+        testing::TestServer::from_router(router)
+            .send_str(r#"{"src":"c0","dest":"n0","body":{"type":"init","node_id":"n0","node_ids":["n0"],"msg_id":1}}"#)
+            .send_str(r#"{"src":"c1","dest":"n0","body":{"type":"txn","txn":[["w",1,5],["w",2,null]],"msg_id":2}}"#)
+            .assert_msg_received_default_timeout(|msg| {
+                matches!(&msg.body, Body::Error(error)
+                    if error.in_reply_to == 2 && error.code == ErrorCode::MalformedRequest)
+            })
+            .send_str(r#"{"src":"c1","dest":"n0","body":{"type":"txn","txn":[["r",1,null]],"msg_id":3}}"#)
+            .assert_msg_received_default_timeout(|msg| {
+                msg.body.decode::<TxnOk>().is_ok_and(|txn_ok| {
+                    txn_ok.in_reply_to == 3 && txn_ok.txn == vec![Op(OpKind::Read, 1, None)]
+                })
+            });
+    }
+
+    #[test]
+    fn should_apply_newer_replicated_writes_only() {
+        let mut router = create_router::<Txn>();
+        insert_txn_handlers(&mut router);
+        testing::TestServer::from_router(router)
+            .send_str(r#"{"src":"c0","dest":"n0","body":{"type":"init","node_id":"n0","node_ids":["n0","n1"],"msg_id":1}}"#)
+            .send_str(r#"{"src":"n1","dest":"n0","body":{"type":"txn_replicate","replications":[{"clock":5,"writes":[[1,50]]}],"msg_id":1}}"#)
+            .send_str(r#"{"src":"n1","dest":"n0","body":{"type":"txn_replicate","replications":[{"clock":3,"writes":[[1,30],[2,30]]}],"msg_id":2}}"#)
+            .assert_msg_received_default_timeout(|msg| msg.body.in_reply_to() == Some(2))
+            .send_str(r#"{"src":"c1","dest":"n0","body":{"type":"txn","txn":[["r",1,null],["r",2,null]],"msg_id":3}}"#)
+            .assert_msg_received_default_timeout(|msg| {
+                msg.body.decode::<TxnOk>().is_ok_and(|txn_ok| {
+                    txn_ok.txn == vec![Op(OpKind::Read, 1, Some(50)), Op(OpKind::Read, 2, Some(30))]
+                })
+            });
+    }
+
+    #[test]
+    fn all_nodes_should_converge_on_the_last_write() {
+        let mut cluster = testing::TestCluster::new(3, || {
+            let mut router = create_router::<Txn>();
+            insert_txn_handlers(&mut router);
+            router
+        });
+        let node_ids = cluster.node_ids().to_vec();
+        let read = TxnRequest {
+            msg_id: 0,
+            txn: vec![Op(OpKind::Read, 1, None)],
+        };
+        let read_all = |cluster: &mut testing::TestCluster| -> Vec<Option<u64>> {
+            node_ids
+                .iter()
+                .map(|node| {
+                    let reply = cluster.request(node, Body::encode(&read).unwrap());
+                    reply.body.decode::<TxnOk>().unwrap().txn[0].2
+                })
+                .collect()
+        };
+
+        // every node sees a write before the next one is made on another node, so the next
+        // write gets a higher clock and has to win
+        for (value, node) in node_ids.iter().enumerate() {
+            let request = TxnRequest {
+                msg_id: 0,
+                txn: vec![Op(OpKind::Write, 1, Some(value as u64))],
+            };
+            cluster.request(node, Body::encode(&request).unwrap());
+            let value = Some(value as u64);
+            cluster.run_until(Duration::from_secs(5), |cluster| {
+                read_all(cluster).iter().all(|read| *read == value)
+            });
+        }
+
+        let last = Some(node_ids.len() as u64 - 1);
+        assert_eq!(read_all(&mut cluster), vec![last; node_ids.len()]);
+    }
+}
@@ -1,19 +1,84 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
 use uuid::Uuid;
 
 use crate::{
-    messages::{Body, Generate, GenerateOk},
-    router::Router,
+    Maelstrom,
+    messages::{Body, ErrorCode, Generate, GenerateOk},
+    router::{HandlerError, Router},
 };
 
 pub fn insert_unique_id_handlers<U>(router: &mut Router<U>) {
-    router.on(|generate: Generate, tx, src, maelstrom, _| {
+    router.on(|generate: Generate, ctx, _| {
         let body = Body::GenerateOk(GenerateOk {
             msg_id: None,
             in_reply_to: generate.msg_id,
-            id: Uuid::new_v4().to_string(),
+            id: Uuid::new_v4().to_string().into(),
         });
-        let msg = maelstrom.create_message(src, body);
-        tx.send(msg).unwrap();
+        ctx.reply(body);
+        Ok(())
+    });
+}
+
+const NODE_BITS: u32 = 10;
+const SEQUENCE_BITS: u32 = 12;
+const SEQUENCE_MASK: u64 = (1 << SEQUENCE_BITS) - 1;
+/// Milliseconds since the unix epoch of 2024-01-01, 41 bits of milliseconds last ~69 years.
+const EPOCH: Duration = Duration::from_millis(1_704_067_200_000);
+
+/// Generates 64-bit ids ordered by creation time: 41 bits of milliseconds since [`EPOCH`],
+/// 10 bits of node index and 12 bits of [`Maelstrom`] counter, the highest bit is always 0.
+/// Nodes never need to talk to each other, ids stay unique under partitions.
+#[derive(Debug, Default)]
+pub struct Snowflake {
+    last_timestamp: u64,
+    last_sequence: Option<u64>,
+}
+
+impl Snowflake {
+    /// `None` before `init` was received or if the cluster has too many nodes.
+    pub fn next_id<U>(&mut self, maelstrom: &mut Maelstrom<U>) -> Option<u64> {
+        let node = maelstrom.node_index()? as u64;
+        if node >> NODE_BITS != 0 {
+            return None;
+        }
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH + EPOCH)
+            .map_or(0, |elapsed| elapsed.as_millis() as u64);
+        Some(self.compose(now, node, maelstrom.generate_id()))
+    }
+
+    fn compose(&mut self, now: u64, node: u64, counter: u64) -> u64 {
+        let sequence = counter & SEQUENCE_MASK;
+        // the timestamp never goes backwards, even if the clock does, and moves forward whenever
+        // the sequence wraps, so the same timestamp and sequence are never handed out twice
+        let mut timestamp = now.max(self.last_timestamp);
+        if self
+            .last_sequence
+            .is_some_and(|last_sequence| sequence <= last_sequence)
+        {
+            timestamp = timestamp.max(self.last_timestamp + 1);
+        }
+        self.last_timestamp = timestamp;
+        self.last_sequence = Some(sequence);
+        (timestamp << (NODE_BITS + SEQUENCE_BITS)) | (node << SEQUENCE_BITS) | sequence
+    }
+}
+
+pub fn insert_snowflake_id_handlers(router: &mut Router<Snowflake>) {
+    router.on(|generate: Generate, ctx, data: &mut Snowflake| {
+        let id = data.next_id(ctx).ok_or_else(|| {
+            HandlerError::new(
+                ErrorCode::TemporarilyUnavailable,
+                "node index unknown or too large for snowflake ids",
+            )
+        })?;
+        ctx.reply(Body::GenerateOk(GenerateOk {
+            msg_id: None,
+            in_reply_to: generate.msg_id,
+            id: id.into(),
+        }));
+        Ok(())
     });
 }
 
@@ -27,7 +92,10 @@ mod tests {
         workloads::init::create_router,
     };
 
-    use super::insert_unique_id_handlers;
+    use super::{
+        SEQUENCE_BITS, SEQUENCE_MASK, Snowflake, insert_snowflake_id_handlers,
+        insert_unique_id_handlers,
+    };
 
     #[test]
     fn should_receive_different_ids_from_generate() {
@@ -41,10 +109,10 @@ mod tests {
 
         let ids: Vec<_> = server
             .get_messages()
-            .into_iter()
+            .iter()
             .filter_map(|msg| {
                 if let Body::GenerateOk(GenerateOk { id, .. }) = &msg.body {
-                    Some(id)
+                    Some(id.to_string())
                 } else {
                     None
                 }
@@ -54,4 +122,44 @@ mod tests {
         assert_eq!(ids.len(), 3);
         assert_eq!(ids.iter().collect::<HashSet<_>>().len(), ids.len());
     }
+
+    #[test]
+    fn snowflake_ids_are_unique_and_increasing_when_sequence_wraps() {
+        let mut snowflake = Snowflake::default();
+        // a frozen clock forces the timestamp forward once all sequence numbers are used
+        let ids: Vec<u64> = (0..3 * (SEQUENCE_MASK + 1))
+            .map(|counter| snowflake.compose(100, 5, counter))
+            .collect();
+        assert!(ids.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(ids[0] >> SEQUENCE_BITS & 0x3ff, 5);
+        assert_eq!(ids.last().unwrap() >> 22, 102);
+
+        // a clock going backwards doesn't produce smaller ids
+        assert!(snowflake.compose(50, 5, 0) > *ids.last().unwrap());
+    }
+
+    #[test]
+    fn should_generate_snowflake_ids_after_init() {
+        let mut router = create_router::<Snowflake>();
+        insert_snowflake_id_handlers(&mut router);
+        let server = testing::TestServer::from_router(router)
+            .send_str(r#"{"src":"c1","dest":"n1","body":{"type":"generate","msg_id":1}}"#)
+            .assert_msg_received_default_timeout(|msg| msg.body.type_name() == "error")
+            .send_str(r#"{"src":"c0","dest":"n1","body":{"type":"init","node_id":"n1","node_ids":["n0","n1"],"msg_id":2}}"#)
+            .send_str(r#"{"src":"c1","dest":"n1","body":{"type":"generate","msg_id":3}}"#)
+            .send_str(r#"{"src":"c1","dest":"n1","body":{"type":"generate","msg_id":4}}"#)
+            .wait_for_messages();
+
+        let ids: Vec<u64> = server
+            .get_messages()
+            .iter()
+            .filter_map(|msg| match &msg.body {
+                Body::GenerateOk(GenerateOk { id, .. }) => id.as_u64(),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(ids.len(), 2);
+        assert!(ids[0] < ids[1]);
+        assert!(ids.iter().all(|id| id >> SEQUENCE_BITS & 0x3ff == 1));
+    }
 }